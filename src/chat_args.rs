@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+/// Settings of one Telegram chat given by --chat.
+/// Settings which are not given are taken from the global options.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ChatArgs {
+    pub id: i64,
+    pub tags: Option<Vec<String>>,
+    pub album: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub photoprism_url: Option<String>,
+    pub photoprism_username: Option<String>,
+    pub photoprism_password: Option<String>,
+    pub immich_url: Option<String>,
+    pub immich_api_key: Option<String>,
+}
+
+impl ChatArgs {
+    const LIST_DELIMITER: char = '|';
+
+    fn parse_list(value: &str) -> Vec<String> {
+        value
+            .split(Self::LIST_DELIMITER)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Parses `id=<chat id>,tags=<tag>|<tag>,album=<album>,labels=<label>|<label>,photoprism-url=<url>,
+/// photoprism-username=<username>,photoprism-password=<password>,immich-url=<url>,immich-api-key=<key>`.
+/// Only `id` is required.
+impl FromStr for ChatArgs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chat_id: Option<i64> = None;
+        let mut chat = ChatArgs::default();
+        // Errors do not repeat the settings, since they may include a password or an API key.
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, got a chat setting without '='."))?;
+            let value = value.trim();
+            match key.trim() {
+                "id" => {
+                    chat_id = Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("Invalid chat id '{}'.", value))?,
+                    )
+                }
+                "tags" => chat.tags = Some(Self::parse_list(value)),
+                "album" => chat.album = Some(value.to_owned()),
+                "labels" => chat.labels = Self::parse_list(value),
+                "photoprism-url" => chat.photoprism_url = Some(value.to_owned()),
+                "photoprism-username" => chat.photoprism_username = Some(value.to_owned()),
+                "photoprism-password" => chat.photoprism_password = Some(value.to_owned()),
+                "immich-url" => chat.immich_url = Some(value.to_owned()),
+                "immich-api-key" => chat.immich_api_key = Some(value.to_owned()),
+                other => return Err(anyhow!("Unknown chat setting '{}'.", other)),
+            }
+        }
+        chat.id = chat_id.ok_or_else(|| anyhow!("Chat setting 'id' is missing."))?;
+        Ok(chat)
+    }
+}
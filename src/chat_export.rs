@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use thiserror::Error;
+
+const RESULT_FILE_NAME: &str = "result.json";
+
+const HASHTAG_ENTITY_TYPE: &str = "hashtag";
+
+/// Message with a photo or a video from a Telegram Desktop "Export chat history" folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedMessage {
+    pub id: i32,
+    /// Time the message has been sent at. Exports made before Telegram Desktop 3.7 do not have it.
+    pub date: Option<DateTime<Utc>>,
+    /// Path of the media file, relative to the export folder.
+    pub file: PathBuf,
+    /// Text of the message without hashtags.
+    pub text: String,
+    /// Hashtags of the message without the leading '#'.
+    pub hashtags: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum ChatExportError {
+    #[error("Failed to read chat export {file}: {err}")]
+    Io { file: String, err: std::io::Error },
+    #[error("Chat export {file} is not a Telegram Desktop JSON export: {err}")]
+    InvalidFormat {
+        file: String,
+        err: serde_json::Error,
+    },
+}
+
+#[derive(Deserialize)]
+struct ExportResponse {
+    messages: Vec<MessageResponse>,
+}
+
+#[derive(Deserialize)]
+struct MessageResponse {
+    id: i32,
+    #[serde(rename = "type")]
+    message_type: String,
+    date_unixtime: Option<String>,
+    photo: Option<String>,
+    file: Option<String>,
+    mime_type: Option<String>,
+    #[serde(default)]
+    text_entities: Vec<TextEntityResponse>,
+}
+
+#[derive(Deserialize)]
+struct TextEntityResponse {
+    #[serde(rename = "type")]
+    entity_type: String,
+    text: String,
+}
+
+impl MessageResponse {
+    fn media_file(&self) -> Option<&str> {
+        if self.message_type != "message" {
+            return None;
+        }
+        self.photo.as_deref().or_else(|| {
+            // Files also include documents, voice messages and stickers.
+            let mime_type = self.mime_type.as_deref()?;
+            if mime_type.starts_with("image/") || mime_type.starts_with("video/") {
+                self.file.as_deref()
+            } else {
+                None
+            }
+        })
+    }
+
+    fn into_exported_message(self) -> Option<ExportedMessage> {
+        let file = PathBuf::from(self.media_file()?);
+        let mut text = String::new();
+        let mut hashtags = Vec::new();
+        for entity in self.text_entities {
+            if entity.entity_type == HASHTAG_ENTITY_TYPE {
+                hashtags.push(entity.text.trim_start_matches('#').to_owned());
+            } else {
+                text.push_str(&entity.text);
+            }
+        }
+        let date = self
+            .date_unixtime
+            .and_then(|date| date.parse().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+        Some(ExportedMessage {
+            id: self.id,
+            date,
+            file,
+            text,
+            hashtags,
+        })
+    }
+}
+
+/// Reads the messages with photos and videos from `result.json` of the export folder, oldest first.
+pub async fn read_chat_export<P: AsRef<Path>>(
+    export_dir: P,
+) -> Result<Vec<ExportedMessage>, ChatExportError> {
+    let path = export_dir.as_ref().join(RESULT_FILE_NAME);
+    let content = tokio::fs::read(&path)
+        .await
+        .map_err(|err| ChatExportError::Io {
+            file: path.display().to_string(),
+            err,
+        })?;
+    let export: ExportResponse =
+        serde_json::from_slice(&content).map_err(|err| ChatExportError::InvalidFormat {
+            file: path.display().to_string(),
+            err,
+        })?;
+    let mut messages: Vec<ExportedMessage> = export
+        .messages
+        .into_iter()
+        .filter_map(MessageResponse::into_exported_message)
+        .collect();
+    messages.sort_by_key(|m| m.id);
+    Ok(messages)
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use moka::future::Cache;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+use telegram2photoprism::{
+    ChatArgs, CompositePhotoService, Destination, DirectoryPhotoService, ImmichPhotoService,
+    PhotoPrismPhotoService, PhotoService,
+};
+
+use crate::Args;
+
+/// Library the files are uploaded to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// PhotoPrism server given by --photoprism-url
+    #[value(name = "photoprism")]
+    #[serde(rename = "photoprism")]
+    PhotoPrism,
+    /// Immich server given by --immich-url
+    Immich,
+    /// Directory given by --directory
+    Directory,
+}
+
+impl Backend {
+    /// Returns the name the backend is given by in the options.
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Where the tags of the tag keyboard come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TagSource {
+    /// Tags given by --tags
+    Static,
+    /// Labels which PhotoPrism already has
+    Labels,
+    /// People (subjects) which PhotoPrism already has. Chosen people are added to the photo as labels,
+    /// since PhotoPrism assigns people to faces it has detected and not to whole photos.
+    People,
+}
+
+/// Everything the bot needs to handle updates of one chat.
+pub struct ChatContext<S> {
+    pub id: ChatId,
+    tags: Vec<String>,
+    tags_source: Vec<TagSource>,
+    // Tags fetched from PhotoPrism, together with the static ones.
+    tags_cache: Cache<(), Arc<Vec<String>>>,
+    /// Title of the album every uploaded file is added to.
+    pub album: Option<String>,
+    /// Labels added to every uploaded file.
+    pub labels: Vec<String>,
+    pub photoservice: Arc<S>,
+}
+
+impl<S: PhotoService> ChatContext<S> {
+    /// Returns the tags to choose from, fetching them from PhotoPrism if it is one of the sources.
+    pub async fn tags(&self) -> Result<Arc<Vec<String>>, anyhow::Error> {
+        let tags = self
+            .tags_cache
+            .try_get_with((), async {
+                let mut tags: Vec<String> = vec![];
+                for source in &self.tags_source {
+                    let source_tags = match source {
+                        TagSource::Static => self.tags.clone(),
+                        TagSource::Labels => self.photoservice.list_labels().await?,
+                        TagSource::People => self.photoservice.list_people().await?,
+                    };
+                    for tag in source_tags {
+                        if !tags.contains(&tag) {
+                            tags.push(tag);
+                        }
+                    }
+                }
+                Ok::<_, S::Error>(Arc::new(tags))
+            })
+            .await?;
+        Ok(tags)
+    }
+}
+
+/// Chats the bot accepts updates from.
+pub struct Chats<S> {
+    chats: HashMap<ChatId, Arc<ChatContext<S>>>,
+}
+
+impl<S: PhotoService> Chats<S> {
+    /// Makes the chats given by the options. The service of every chat is made by `new_photoservice`.
+    pub fn new(
+        args: &Args,
+        mut new_photoservice: impl FnMut(&ChatArgs) -> Result<Arc<S>, anyhow::Error>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut chat_args = args.chat.clone();
+        if let Some(telegram_chat_id) = args.telegram_chat_id {
+            chat_args.push(ChatArgs {
+                id: telegram_chat_id,
+                ..ChatArgs::default()
+            });
+        }
+        if chat_args.is_empty() {
+            return Err(anyhow!(
+                "No chat is given. Use --telegram-chat-id, --chat or [[chat]] in the config file."
+            ));
+        }
+
+        let mut chats = HashMap::new();
+        for chat in chat_args {
+            let photoservice = new_photoservice(&chat)?;
+            let chat_context = ChatContext {
+                id: ChatId(chat.id),
+                tags: chat.tags.unwrap_or_else(|| args.tags.clone()),
+                tags_source: args.tags_source.clone(),
+                tags_cache: Cache::builder()
+                    .max_capacity(1)
+                    .time_to_live(Duration::from_secs(args.tags_cache_ttl_sec))
+                    .build(),
+                album: chat.album,
+                labels: chat.labels,
+                photoservice,
+            };
+            if chats
+                .insert(chat_context.id, Arc::new(chat_context))
+                .is_some()
+            {
+                return Err(anyhow!("Chat {} is given more than once.", chat.id));
+            }
+        }
+
+        Ok(Self { chats })
+    }
+
+    pub fn get(&self, chat_id: ChatId) -> Option<Arc<ChatContext<S>>> {
+        self.chats.get(&chat_id).cloned()
+    }
+
+    /// Returns the services of the chats. Chats sharing an account share the service.
+    pub fn photoservices(&self) -> Vec<Arc<S>> {
+        let mut photoservices: Vec<Arc<S>> = Vec::new();
+        for chat in self.chats.values() {
+            if !photoservices
+                .iter()
+                .any(|photoservice| Arc::ptr_eq(photoservice, &chat.photoservice))
+            {
+                photoservices.push(chat.photoservice.clone());
+            }
+        }
+        photoservices
+    }
+
+    /// Returns the chat if the bot watches only one.
+    pub fn single(&self) -> Option<Arc<ChatContext<S>>> {
+        match self.chats.len() {
+            1 => self.chats.values().next().cloned(),
+            _ => None,
+        }
+    }
+}
+
+/// Makes the PhotoPrism service of every chat from its settings and the global options.
+/// Chats with the same PhotoPrism account share the service and its session, so they must have the same password.
+pub fn photoprism_services(
+    args: &Args,
+) -> impl FnMut(&ChatArgs) -> Result<Arc<PhotoPrismPhotoService>, anyhow::Error> + '_ {
+    // Services with their passwords by URL and username.
+    let mut photoservices: HashMap<(String, String), (String, Arc<PhotoPrismPhotoService>)> =
+        HashMap::new();
+    move |chat| {
+        let setting = |chat_value: &Option<String>, global_value: &Option<String>, name| {
+            chat_value
+                .as_ref()
+                .or(global_value.as_ref())
+                .cloned()
+                .ok_or_else(|| anyhow!("{} is not set for chat {}.", name, chat.id))
+        };
+        let url = setting(&chat.photoprism_url, &args.photoprism_url, "PhotoPrism URL")?;
+        let username = setting(
+            &chat.photoprism_username,
+            &args.photoprism_username,
+            "PhotoPrism username",
+        )?;
+        let password = setting(
+            &chat.photoprism_password,
+            &args.photoprism_password,
+            "PhotoPrism password",
+        )?;
+        let (service_password, photoservice) = photoservices
+            .entry((url.clone(), username.clone()))
+            .or_insert_with(|| {
+                let photoservice = PhotoPrismPhotoService::new(
+                    url.clone(),
+                    username.clone(),
+                    password.clone(),
+                    args.photoprism_session_refresh_sec,
+                );
+                (password.clone(), Arc::new(photoservice))
+            });
+        if *service_password != password {
+            return Err(anyhow!(
+                "Chat {} has another password for PhotoPrism user {} at {} than the chats before.",
+                chat.id,
+                username,
+                url
+            ));
+        }
+        Ok(photoservice.clone())
+    }
+}
+
+/// Makes the Immich service of every chat from its settings and the global options.
+/// Chats with the same Immich API key share the service.
+pub fn immich_services(
+    args: &Args,
+) -> impl FnMut(&ChatArgs) -> Result<Arc<ImmichPhotoService>, anyhow::Error> + '_ {
+    let mut photoservices: HashMap<(String, String), Arc<ImmichPhotoService>> = HashMap::new();
+    move |chat| {
+        let url = chat
+            .immich_url
+            .as_ref()
+            .or(args.immich_url.as_ref())
+            .cloned()
+            .ok_or_else(|| anyhow!("Immich URL is not set for chat {}.", chat.id))?;
+        let api_key = chat
+            .immich_api_key
+            .as_ref()
+            .or(args.immich_api_key.as_ref())
+            .cloned()
+            .ok_or_else(|| anyhow!("Immich API key is not set for chat {}.", chat.id))?;
+        Ok(photoservices
+            .entry((url.clone(), api_key.clone()))
+            .or_insert_with(|| Arc::new(ImmichPhotoService::new(url, api_key)))
+            .clone())
+    }
+}
+
+/// Makes the directory service shared by all the chats.
+pub fn directory_services(
+    args: &Args,
+) -> impl FnMut(&ChatArgs) -> Result<Arc<DirectoryPhotoService>, anyhow::Error> {
+    let photoservice = args
+        .directory
+        .clone()
+        .map(|directory| Arc::new(DirectoryPhotoService::new(directory)));
+    move |_: &ChatArgs| {
+        photoservice
+            .clone()
+            .ok_or_else(|| anyhow!("The directory backend requires --directory."))
+    }
+}
+
+/// Makes the service of every chat uploading to all the backends given by --backend, in their order.
+/// Chats share the services of the backends like they do with a single backend.
+pub fn composite_services(
+    args: &Args,
+) -> Result<
+    impl FnMut(&ChatArgs) -> Result<Arc<CompositePhotoService>, anyhow::Error> + '_,
+    anyhow::Error,
+> {
+    if args.backend.is_empty() {
+        return Err(anyhow!("No backend is given."));
+    }
+    for (index, backend) in args.backend.iter().enumerate() {
+        if args.backend[..index].contains(backend) {
+            return Err(anyhow!(
+                "Backend {} is given more than once.",
+                backend.name()
+            ));
+        }
+    }
+    let mut photoprism = photoprism_services(args);
+    let mut immich = immich_services(args);
+    let mut directory = directory_services(args);
+    Ok(move |chat: &ChatArgs| {
+        let mut targets = vec![];
+        for backend in &args.backend {
+            let destination = match backend {
+                Backend::PhotoPrism => Destination::PhotoPrism(photoprism(chat)?),
+                Backend::Immich => Destination::Immich(immich(chat)?),
+                Backend::Directory => Destination::Directory(directory(chat)?),
+            };
+            targets.push((backend.name(), destination));
+        }
+        Ok(Arc::new(CompositePhotoService::new(targets)))
+    })
+}
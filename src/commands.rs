@@ -0,0 +1,269 @@
+use chrono::Utc;
+use log::warn;
+use teloxide::prelude::*;
+use teloxide::types::{BotCommand, MessageId};
+use teloxide::utils::command::BotCommands;
+
+use telegram2photoprism::{PhotoService, PhotoUID};
+
+use crate::chats::ChatContext;
+use crate::{
+    enqueue_upload, find_upload_records, get_file, make_tags_keyboard, ApplicationContext, Bot,
+    TagKeyboardState,
+};
+
+/// Commands of the bot. Their descriptions are taken from the locale.
+#[derive(BotCommands, Clone, Copy, Debug)]
+#[command(rename_rule = "lowercase")]
+pub enum ChatCommand {
+    Help,
+    Status,
+    Retry,
+    Tags,
+    Delete,
+}
+
+/// Registers the commands, so Telegram suggests them in the chat.
+pub async fn register_commands(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+) -> Result<(), anyhow::Error> {
+    let commands: Vec<BotCommand> = ChatCommand::bot_commands()
+        .into_iter()
+        .filter(|command| app_context.archive_on_delete || command.command != "/delete")
+        .map(|command| {
+            let name = command.command.trim_start_matches('/').to_owned();
+            let description = t!(&format!("command-{}", name)).into_owned();
+            BotCommand::new(name, description)
+        })
+        .collect();
+    bot.set_my_commands(commands).await?;
+    Ok(())
+}
+
+pub async fn handle_command(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<impl PhotoService>,
+    m: &Message,
+    command: ChatCommand,
+) -> Result<(), anyhow::Error> {
+    match command {
+        ChatCommand::Help => {
+            bot.send_message(m.chat.id, t!("help"))
+                .reply_to_message_id(m.id)
+                .await?;
+        }
+        ChatCommand::Status => handle_status_command(bot, app_context, chat, m).await?,
+        ChatCommand::Retry => handle_retry_command(bot, app_context, chat, m).await?,
+        ChatCommand::Tags => handle_tags_command(bot, app_context, chat, m).await?,
+        ChatCommand::Delete => handle_delete_command(bot, app_context, chat, m).await?,
+    }
+    Ok(())
+}
+
+async fn handle_status_command(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<impl PhotoService>,
+    m: &Message,
+) -> Result<(), anyhow::Error> {
+    let photoprism_status = match chat.photoservice.check_status().await {
+        Ok(()) => t!("status-photoprism-available"),
+        Err(err) => t!("status-photoprism-unavailable", error = err.to_string()),
+    };
+    let jobs = app_context.upload_queue.jobs().await;
+    let chat_jobs: Vec<_> = jobs
+        .iter()
+        .filter(|job| job.payload.chat_id == chat.id.0)
+        .collect();
+    let failed_count = chat_jobs.iter().filter(|job| job.attempts > 0).count();
+    let queue_status = t!(
+        "status-queue",
+        count = chat_jobs.len(),
+        failed = failed_count
+    );
+    bot.send_message(
+        m.chat.id,
+        format!("{}\n{}", photoprism_status, queue_status),
+    )
+    .reply_to_message_id(m.id)
+    .await?;
+    Ok(())
+}
+
+/// Retries the upload of the replied message. A queued upload is attempted right away,
+/// a message which is not queued anymore is uploaded again.
+async fn handle_retry_command(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<impl PhotoService>,
+    m: &Message,
+) -> Result<(), anyhow::Error> {
+    let Some(replied) = m.reply_to_message() else {
+        bot.send_message(m.chat.id, t!("retry-reply-required"))
+            .reply_to_message_id(m.id)
+            .await?;
+        return Ok(());
+    };
+
+    let queued_job = app_context
+        .upload_queue
+        .jobs()
+        .await
+        .into_iter()
+        .find(|job| {
+            job.payload.chat_id == chat.id.0
+                && (job.payload.status_message_id == replied.id.0
+                    || job.payload.messages.iter().any(|q| q.id == replied.id))
+        });
+    if let Some(job) = queued_job {
+        app_context
+            .upload_queue
+            .reschedule(job.id, Utc::now())
+            .await?;
+        app_context.upload_queue_changed.notify_one();
+        if job.attempts > 0 {
+            bot.edit_message_text(
+                chat.id,
+                MessageId(job.payload.status_message_id),
+                t!("upload-started"),
+            )
+            .await?;
+        }
+    } else if get_file(replied).is_some() {
+        enqueue_upload(bot, app_context, vec![replied.clone()]).await?;
+    } else {
+        bot.send_message(m.chat.id, t!("retry-reply-required"))
+            .reply_to_message_id(m.id)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Opens a new tag keyboard for the uploaded files of the replied message,
+/// with the labels the files already have selected.
+async fn handle_tags_command(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<impl PhotoService>,
+    m: &Message,
+) -> Result<(), anyhow::Error> {
+    let Some(replied) = m.reply_to_message() else {
+        bot.send_message(m.chat.id, t!("tags-reply-required"))
+            .reply_to_message_id(m.id)
+            .await?;
+        return Ok(());
+    };
+    let records = find_upload_records(app_context, chat.id, replied.id).await;
+    if records.is_empty() {
+        bot.send_message(m.chat.id, t!("file-not-uploaded"))
+            .reply_to_message_id(m.id)
+            .await?;
+        return Ok(());
+    }
+
+    // Labels which all the files have are shown as selected.
+    let mut labels: Option<Vec<String>> = None;
+    for record in &records {
+        let photo_labels = chat
+            .photoservice
+            .list_photo_labels(&record.photo_uid)
+            .await?;
+        labels = Some(match labels {
+            Some(labels) => labels
+                .into_iter()
+                .filter(|label| photo_labels.contains(label))
+                .collect(),
+            None => photo_labels,
+        });
+    }
+    let state = TagKeyboardState::with_labels(chat.tags().await?, &labels.unwrap_or_default());
+    if state.tags.is_empty() {
+        bot.send_message(m.chat.id, t!("no-tags"))
+            .reply_to_message_id(m.id)
+            .await?;
+        return Ok(());
+    }
+
+    // The keyboard is a reply to the upload, so the files are found when the tags are saved.
+    let keyboard_message = bot
+        .send_message(m.chat.id, t!("choose-tags"))
+        .reply_to_message_id(replied.id)
+        .reply_markup(make_tags_keyboard(&state, app_context.tags_page_size))
+        .await?;
+    app_context
+        .tag_keyboards
+        .insert((keyboard_message.chat.id, keyboard_message.id), state)
+        .await;
+    Ok(())
+}
+
+/// Deletes the replied photo or video from the chat and moves it to the PhotoPrism archive.
+async fn handle_delete_command(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<impl PhotoService>,
+    m: &Message,
+) -> Result<(), anyhow::Error> {
+    let reply = |text| bot.send_message(m.chat.id, text).reply_to_message_id(m.id);
+    if !app_context.archive_on_delete {
+        reply(t!("delete-disabled")).await?;
+        return Ok(());
+    }
+    if !is_admin(bot, m).await? {
+        reply(t!("delete-admin-only")).await?;
+        return Ok(());
+    }
+    let Some(replied) = m.reply_to_message() else {
+        reply(t!("delete-reply-required")).await?;
+        return Ok(());
+    };
+    let records = find_upload_records(app_context, chat.id, replied.id).await;
+    if records.is_empty() {
+        reply(t!("file-not-uploaded")).await?;
+        return Ok(());
+    }
+
+    let mut photo_uids: Vec<PhotoUID> = Vec::with_capacity(records.len());
+    // The media messages of the files, and the status message if it is the replied one.
+    let mut message_ids: Vec<MessageId> = vec![replied.id];
+    for record in records {
+        if !photo_uids.contains(&record.photo_uid) {
+            photo_uids.push(record.photo_uid);
+        }
+        if !message_ids.contains(&MessageId(record.message_id)) {
+            message_ids.push(MessageId(record.message_id));
+        }
+    }
+    chat.photoservice.archive_photos(&photo_uids).await?;
+    // Archived photos can not be tagged or added to albums anymore.
+    app_context.message_store.remove_photos(&photo_uids).await?;
+    for message_id in message_ids {
+        // The bot can delete messages of other members only if it is an admin of the chat.
+        if let Err(err) = bot.delete_message(chat.id, message_id).await {
+            warn!("Failed to delete message {}: {}", message_id, err);
+        }
+    }
+    reply(t!("success-archived", count = photo_uids.len())).await?;
+    Ok(())
+}
+
+async fn is_admin(bot: &Bot, m: &Message) -> Result<bool, anyhow::Error> {
+    if m.chat.is_private() {
+        return Ok(true);
+    }
+    // Anonymous admins send messages on behalf of the chat.
+    if m.sender_chat()
+        .is_some_and(|sender_chat| sender_chat.id == m.chat.id)
+    {
+        return Ok(true);
+    }
+    let Some(user) = m.from() else {
+        return Ok(false);
+    };
+    Ok(bot
+        .get_chat_member(m.chat.id, user.id)
+        .await?
+        .is_privileged())
+}
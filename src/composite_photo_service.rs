@@ -0,0 +1,353 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use log::warn;
+use thiserror::Error;
+
+use crate::{
+    calculate_sha1, Album, DirectoryPhotoService, DirectoryServiceError, ImmichPhotoService,
+    ImmichServiceError, PhotoDetails, PhotoPrismPhotoService, PhotoPrismServiceError, PhotoService,
+    PhotoUID,
+};
+
+#[derive(Error, Debug)]
+pub enum CompositeServiceError {
+    #[error("PhotoPrism: {0}")]
+    PhotoPrism(#[from] PhotoPrismServiceError),
+    #[error("Immich: {0}")]
+    Immich(#[from] ImmichServiceError),
+    #[error("Directory: {0}")]
+    Directory(#[from] DirectoryServiceError),
+    #[error("Failed to upload file {file} to any target: {details}")]
+    UploadFailed { file: String, details: String },
+    #[error("File with uid {0} is not stored at any target.")]
+    UnknownPhotoUID(String),
+    #[error("Album with uid {0} has not been found.")]
+    AlbumNotFound(String),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Library a [`CompositePhotoService`] stores the files at.
+pub enum Destination {
+    PhotoPrism(Arc<PhotoPrismPhotoService>),
+    Immich(Arc<ImmichPhotoService>),
+    Directory(Arc<DirectoryPhotoService>),
+}
+
+// Calls the method of the service behind the destination, converting the error.
+macro_rules! dispatch {
+    ($destination:expr, $service:ident => $call:expr) => {
+        match $destination {
+            Destination::PhotoPrism($service) => $call.await.map_err(CompositeServiceError::from),
+            Destination::Immich($service) => $call.await.map_err(CompositeServiceError::from),
+            Destination::Directory($service) => $call.await.map_err(CompositeServiceError::from),
+        }
+    };
+}
+
+struct Target {
+    name: String,
+    destination: Destination,
+}
+
+/// Stores every file at several libraries, e.g. PhotoPrism and a directory backup.
+///
+/// The photo UID lists the UIDs of the photo at every target as `<target>=<uid>;<target>=<uid>`.
+/// A UID without target names has been recorded with a single backend and belongs to the first target.
+/// An upload succeeds if the file is stored at one of the targets at least. Targets which have failed
+/// are missing in the UID, and [`PhotoService::find_photo_by_hash`] does not find the photo until it
+/// is uploaded again. Changes are applied at every target which has the photo, and the labels and
+/// the date of a photo are read from the first one having it. Albums, labels and people are read
+/// from the first target.
+pub struct CompositePhotoService {
+    targets: Vec<Target>,
+}
+
+impl CompositePhotoService {
+    const UID_SEPARATOR: char = ';';
+
+    /// Panics if no target is given.
+    pub fn new(targets: Vec<(String, Destination)>) -> Self {
+        assert!(!targets.is_empty(), "A composite service needs a target.");
+        Self {
+            targets: targets
+                .into_iter()
+                .map(|(name, destination)| Target { name, destination })
+                .collect(),
+        }
+    }
+
+    fn primary(&self) -> &Destination {
+        &self.targets[0].destination
+    }
+
+    fn encode_uid(uids: &[(&Target, PhotoUID)]) -> PhotoUID {
+        PhotoUID(
+            uids.iter()
+                .map(|(target, uid)| format!("{}={}", target.name, uid.0))
+                .collect::<Vec<_>>()
+                .join(&Self::UID_SEPARATOR.to_string()),
+        )
+    }
+
+    /// Returns the targets which have the photo together with its UID there, in the order of the targets.
+    fn decode_uid(
+        &self,
+        photo_uid: &PhotoUID,
+    ) -> Result<Vec<(&Target, PhotoUID)>, CompositeServiceError> {
+        if !photo_uid.0.contains('=') {
+            return Ok(vec![(&self.targets[0], photo_uid.clone())]);
+        }
+        let uids: Vec<(&Target, PhotoUID)> = photo_uid
+            .0
+            .split(Self::UID_SEPARATOR)
+            .filter_map(|part| part.split_once('='))
+            .filter_map(|(name, uid)| {
+                self.targets
+                    .iter()
+                    .find(|target| target.name == name)
+                    .map(|target| (target, PhotoUID(uid.to_owned())))
+            })
+            .collect();
+        match uids.is_empty() {
+            true => Err(CompositeServiceError::UnknownPhotoUID(
+                photo_uid.0.to_owned(),
+            )),
+            false => Ok(uids),
+        }
+    }
+
+    /// Returns the first target which has the photo together with its UID there.
+    fn first_stored(
+        &self,
+        photo_uid: &PhotoUID,
+    ) -> Result<(&Target, PhotoUID), CompositeServiceError> {
+        let mut uids = self.decode_uid(photo_uid)?;
+        Ok(uids.remove(0))
+    }
+
+    /// Returns the UIDs at the target of the photos it has.
+    fn uids_at(
+        &self,
+        target: &Target,
+        photo_uids: &[PhotoUID],
+    ) -> Result<Vec<PhotoUID>, CompositeServiceError> {
+        let mut target_uids = vec![];
+        for photo_uid in photo_uids {
+            for (uid_target, uid) in self.decode_uid(photo_uid)? {
+                if std::ptr::eq(uid_target, target) {
+                    target_uids.push(uid);
+                }
+            }
+        }
+        Ok(target_uids)
+    }
+
+    /// Uploads the file to the target unless it already has the file.
+    async fn upload_to(
+        destination: &Destination,
+        file_path: &Path,
+        file_hash: &str,
+    ) -> Result<PhotoUID, CompositeServiceError> {
+        match dispatch!(destination, service => service.find_photo_by_hash(file_hash))? {
+            Some(uid) => Ok(uid),
+            None => {
+                dispatch!(destination, service => service.upload_photo_with_hash(file_path, file_hash))
+            }
+        }
+    }
+
+    /// Finds the album with the title at the target, creating it if there is none.
+    async fn find_or_create_album(
+        destination: &Destination,
+        title: &str,
+    ) -> Result<Album, CompositeServiceError> {
+        let albums = dispatch!(destination, service => service.list_albums())?;
+        match albums.into_iter().find(|album| album.title == title) {
+            Some(album) => Ok(album),
+            None => dispatch!(destination, service => service.create_album(title)),
+        }
+    }
+}
+
+impl PhotoService for CompositePhotoService {
+    type Error = CompositeServiceError;
+
+    async fn check_status(&self) -> Result<(), Self::Error> {
+        for target in &self.targets {
+            dispatch!(&target.destination, service => service.check_status())?;
+        }
+        Ok(())
+    }
+
+    async fn check_access(&self) -> Result<(), Self::Error> {
+        for target in &self.targets {
+            dispatch!(&target.destination, service => service.check_access())?;
+        }
+        Ok(())
+    }
+
+    async fn upload_photo<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+    ) -> Result<PhotoUID, Self::Error> {
+        let file_hash = calculate_sha1(&file_path).await?;
+        self.upload_photo_with_hash(file_path, &file_hash).await
+    }
+
+    /// Uploads the file to the targets which do not have it yet.
+    async fn upload_photo_with_hash<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+        file_hash: &str,
+    ) -> Result<PhotoUID, Self::Error> {
+        let mut uids = vec![];
+        let mut errors = vec![];
+        for target in &self.targets {
+            let result = Self::upload_to(&target.destination, file_path.as_ref(), file_hash).await;
+            match result {
+                Ok(uid) => uids.push((target, uid)),
+                Err(err) => {
+                    warn!("Failed to upload file to {}: {}", target.name, err);
+                    errors.push(err.to_string());
+                }
+            }
+        }
+        if uids.is_empty() {
+            return Err(CompositeServiceError::UploadFailed {
+                file: file_path.as_ref().display().to_string(),
+                details: errors.join(", "),
+            });
+        }
+        Ok(Self::encode_uid(&uids))
+    }
+
+    /// Returns the photo only if every target has it. A target which fails to look for the photo
+    /// is taken as not having it, so the file is uploaded to the other targets.
+    async fn find_photo_by_hash(&self, file_hash: &str) -> Result<Option<PhotoUID>, Self::Error> {
+        let mut uids = vec![];
+        for target in &self.targets {
+            match dispatch!(&target.destination, service => service.find_photo_by_hash(file_hash)) {
+                Ok(Some(uid)) => uids.push((target, uid)),
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    warn!("Failed to find file at {}: {}", target.name, err);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(Self::encode_uid(&uids)))
+    }
+
+    async fn add_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        for (target, uid) in self.decode_uid(photo_uid)? {
+            dispatch!(&target.destination, service => service.add_label(&uid, label))?;
+        }
+        Ok(())
+    }
+
+    async fn remove_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        for (target, uid) in self.decode_uid(photo_uid)? {
+            dispatch!(&target.destination, service => service.remove_label(&uid, label))?;
+        }
+        Ok(())
+    }
+
+    async fn list_photo_labels(&self, photo_uid: &PhotoUID) -> Result<Vec<String>, Self::Error> {
+        let (target, uid) = self.first_stored(photo_uid)?;
+        dispatch!(&target.destination, service => service.list_photo_labels(&uid))
+    }
+
+    async fn has_capture_date(&self, photo_uid: &PhotoUID) -> Result<bool, Self::Error> {
+        let (target, uid) = self.first_stored(photo_uid)?;
+        dispatch!(&target.destination, service => service.has_capture_date(&uid))
+    }
+
+    async fn update_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        details: &PhotoDetails,
+    ) -> Result<(), Self::Error> {
+        for (target, uid) in self.decode_uid(photo_uid)? {
+            dispatch!(&target.destination, service => service.update_photo(&uid, details))?;
+        }
+        Ok(())
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>, Self::Error> {
+        dispatch!(self.primary(), service => service.list_albums())
+    }
+
+    async fn create_album(&self, title: &str) -> Result<Album, Self::Error> {
+        let album = dispatch!(self.primary(), service => service.create_album(title))?;
+        for target in &self.targets[1..] {
+            Self::find_or_create_album(&target.destination, title).await?;
+        }
+        Ok(album)
+    }
+
+    /// Adds the photos to the album of the first target and to the albums with the same title at the others.
+    async fn add_to_album(
+        &self,
+        album_uid: &str,
+        photo_uids: &[PhotoUID],
+    ) -> Result<(), Self::Error> {
+        let albums = self.list_albums().await?;
+        let title = albums
+            .into_iter()
+            .find(|album| album.uid == album_uid)
+            .map(|album| album.title)
+            .ok_or_else(|| CompositeServiceError::AlbumNotFound(album_uid.to_owned()))?;
+        for (index, target) in self.targets.iter().enumerate() {
+            let target_uids = self.uids_at(target, photo_uids)?;
+            if target_uids.is_empty() {
+                continue;
+            }
+            let target_album_uid = match index {
+                0 => album_uid.to_owned(),
+                _ => {
+                    Self::find_or_create_album(&target.destination, &title)
+                        .await?
+                        .uid
+                }
+            };
+            dispatch!(
+                &target.destination,
+                service => service.add_to_album(&target_album_uid, &target_uids)
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn archive_photos(&self, photo_uids: &[PhotoUID]) -> Result<(), Self::Error> {
+        for target in &self.targets {
+            let target_uids = self.uids_at(target, photo_uids)?;
+            if !target_uids.is_empty() {
+                dispatch!(&target.destination, service => service.archive_photos(&target_uids))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_labels(&self) -> Result<Vec<String>, Self::Error> {
+        dispatch!(self.primary(), service => service.list_labels())
+    }
+
+    async fn list_people(&self) -> Result<Vec<String>, Self::Error> {
+        dispatch!(self.primary(), service => service.list_people())
+    }
+
+    fn stored_at(&self, photo_uid: &PhotoUID) -> Vec<(String, bool)> {
+        let uids = self.decode_uid(photo_uid).unwrap_or_default();
+        self.targets
+            .iter()
+            .map(|target| {
+                let is_stored = uids
+                    .iter()
+                    .any(|(uid_target, _)| std::ptr::eq(*uid_target, target));
+                (target.name.to_owned(), is_stored)
+            })
+            .collect()
+    }
+}
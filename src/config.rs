@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+
+use telegram2photoprism::ChatArgs;
+
+use crate::chats::{Backend, TagSource};
+use crate::Args;
+
+/// Options read from the file given by --config. Keys are the names of the command line options
+/// without the leading dashes, chats are given as `[[chat]]` tables.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    telegram_access_token: Option<String>,
+    telegram_chat_id: Option<i64>,
+    chat: Option<Vec<ChatArgs>>,
+    telegram_bot_api_server: Option<String>,
+    tags: Option<Vec<String>>,
+    tags_source: Option<Vec<TagSource>>,
+    tags_cache_ttl_sec: Option<u64>,
+    backend: Option<Vec<Backend>>,
+    immich_url: Option<String>,
+    immich_api_key: Option<String>,
+    directory: Option<PathBuf>,
+    photoprism_url: Option<String>,
+    photoprism_username: Option<String>,
+    photoprism_password: Option<String>,
+    photoprism_session_refresh_sec: Option<u64>,
+    locale: Option<String>,
+    working_dir: Option<PathBuf>,
+    disallow_compressed_files: Option<bool>,
+    media_group_timeout_ms: Option<u64>,
+    caption_as_title: Option<bool>,
+    tags_page_size: Option<u16>,
+    choose_album: Option<bool>,
+    archive_on_delete: Option<bool>,
+    stream_uploads: Option<bool>,
+    listen_address: Option<SocketAddr>,
+    webhook_url: Option<String>,
+    webhook_listen: Option<SocketAddr>,
+    webhook_secret_token: Option<String>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read config file {}: {}", path.display(), err))?;
+        toml::from_str(&content)
+            .map_err(|err| anyhow!("Invalid config file {}: {}", path.display(), err))
+    }
+}
+
+// Takes the value of every option from the config file unless it is given on the command line
+// or by an environment variable.
+macro_rules! merge {
+    ($args:ident, $matches:ident, $file:ident, $($field:ident),+) => {
+        $(
+            if let Some(value) = $file.$field {
+                if !is_given($matches, stringify!($field)) {
+                    $args.$field = value.into();
+                }
+            }
+        )+
+    };
+}
+
+fn is_given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+impl Args {
+    /// Parses the command line and the environment, then fills the options which are not given
+    /// there from the config file.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches)?;
+        if let Some(config) = args.config.clone() {
+            let file = ConfigFile::read(&config)?;
+            args.merge(&matches, file);
+            args.validate()
+                .map_err(|err| anyhow!("Invalid config file {}: {}", config.display(), err))?;
+        }
+        Ok(args)
+    }
+
+    fn merge(&mut self, matches: &ArgMatches, file: ConfigFile) {
+        merge!(
+            self,
+            matches,
+            file,
+            telegram_access_token,
+            telegram_chat_id,
+            chat,
+            telegram_bot_api_server,
+            tags,
+            tags_source,
+            tags_cache_ttl_sec,
+            backend,
+            immich_url,
+            immich_api_key,
+            directory,
+            photoprism_url,
+            photoprism_username,
+            photoprism_password,
+            photoprism_session_refresh_sec,
+            locale,
+            working_dir,
+            disallow_compressed_files,
+            media_group_timeout_ms,
+            caption_as_title,
+            tags_page_size,
+            choose_album,
+            archive_on_delete,
+            stream_uploads,
+            listen_address,
+            webhook_url,
+            webhook_listen,
+            webhook_secret_token
+        );
+    }
+
+    // Checks the values clap would have rejected if they had been given on the command line.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if !available_locales!().contains(&self.locale.as_str()) {
+            return Err(anyhow!(
+                "unsupported value '{}' for key `locale`, expected one of: {}",
+                self.locale,
+                available_locales!().join(", ")
+            ));
+        }
+        if self.backend.is_empty() {
+            return Err(anyhow!("key `backend` must list at least one backend"));
+        }
+        if !(1..=90).contains(&self.tags_page_size) {
+            return Err(anyhow!(
+                "value {} for key `tags-page-size` is not in 1..=90",
+                self.tags_page_size
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{calculate_sha1, Album, PhotoDetails, PhotoService, PhotoUID};
+
+#[derive(Error, Debug)]
+pub enum DirectoryServiceError {
+    #[error("Library directory {0} does not exist.")]
+    DirectoryNotFound(String),
+    #[error("File with uid {0} has not been found in the library.")]
+    PhotoNotFound(String),
+    #[error("Invalid album title {0}.")]
+    InvalidAlbumTitle(String),
+    #[error("Failed to access library directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Library kept as plain files in a directory tree organised by date: `<year>/<month>/<sha1>.<extension>`.
+///
+/// Title, description, date and labels of every file are written to an XMP sidecar next to it,
+/// so PhotoPrism and other tools pick them up when the directory is imported.
+/// The SHA1 of a file is its photo UID. Albums are directories in `Albums` with hard links to the files,
+/// archived files are moved to `Archive`.
+pub struct DirectoryPhotoService {
+    root: PathBuf,
+    // Paths of the files relative to the root by photo UID. The tree is scanned on first use.
+    index: Mutex<Option<HashMap<PhotoUID, PathBuf>>>,
+}
+
+impl DirectoryPhotoService {
+    const ALBUMS_DIR: &'static str = "Albums";
+
+    const ARCHIVE_DIR: &'static str = "Archive";
+
+    const SIDECAR_EXTENSION: &'static str = "xmp";
+
+    const DATE_DIR_FORMAT: &'static str = "%Y/%m";
+
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            index: Mutex::new(None),
+        }
+    }
+
+    async fn with_index<T>(
+        &self,
+        f: impl FnOnce(&mut HashMap<PhotoUID, PathBuf>) -> T,
+    ) -> Result<T, DirectoryServiceError> {
+        let mut index = self.index.lock().await;
+        if index.is_none() {
+            let root = self.root.clone();
+            let scanned = tokio::task::spawn_blocking(move || Self::scan(&root))
+                .await
+                .map_err(anyhow::Error::from)??;
+            *index = Some(scanned);
+        }
+        Ok(f(index.as_mut().unwrap()))
+    }
+
+    /// Finds the files of the library, skipping albums and the archive.
+    fn scan(root: &Path) -> Result<HashMap<PhotoUID, PathBuf>, std::io::Error> {
+        let mut index = HashMap::new();
+        if !root.is_dir() {
+            return Ok(index);
+        }
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(root.join(&dir))? {
+                let entry = entry?;
+                let relative_path = dir.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    if relative_path != Path::new(Self::ALBUMS_DIR)
+                        && relative_path != Path::new(Self::ARCHIVE_DIR)
+                    {
+                        dirs.push(relative_path);
+                    }
+                    continue;
+                }
+                if relative_path
+                    .extension()
+                    .is_some_and(|ext| ext == Self::SIDECAR_EXTENSION)
+                {
+                    continue;
+                }
+                let Some(stem) = relative_path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                if stem.len() == 40 && stem.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    index.insert(PhotoUID(stem.to_owned()), relative_path);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    async fn find_path(&self, photo_uid: &PhotoUID) -> Result<PathBuf, DirectoryServiceError> {
+        self.with_index(|index| index.get(photo_uid).cloned())
+            .await?
+            .map(|path| self.root.join(path))
+            .ok_or_else(|| DirectoryServiceError::PhotoNotFound(photo_uid.0.to_owned()))
+    }
+
+    fn sidecar_path(path: &Path) -> PathBuf {
+        path.with_extension(Self::SIDECAR_EXTENSION)
+    }
+
+    async fn read_sidecar(path: &Path) -> Result<Sidecar, DirectoryServiceError> {
+        match tokio::fs::read_to_string(Self::sidecar_path(path)).await {
+            Ok(xmp) => Ok(Sidecar::parse(&xmp)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Sidecar::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write_sidecar(path: &Path, sidecar: &Sidecar) -> Result<(), DirectoryServiceError> {
+        tokio::fs::write(Self::sidecar_path(path), sidecar.to_xmp()).await?;
+        Ok(())
+    }
+
+    /// Moves the file and its sidecar to the directory, relative to the root.
+    async fn move_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        target_dir: &Path,
+    ) -> Result<PathBuf, DirectoryServiceError> {
+        let path = self.find_path(photo_uid).await?;
+        let target_path = target_dir.join(path.file_name().unwrap_or_default());
+        tokio::fs::create_dir_all(self.root.join(target_dir)).await?;
+        tokio::fs::rename(&path, self.root.join(&target_path)).await?;
+        let sidecar_path = Self::sidecar_path(&path);
+        if tokio::fs::try_exists(&sidecar_path).await? {
+            tokio::fs::rename(
+                sidecar_path,
+                Self::sidecar_path(&self.root.join(&target_path)),
+            )
+            .await?;
+        }
+        Ok(target_path)
+    }
+
+    fn album_dir(&self, album_uid: &str) -> PathBuf {
+        self.root.join(Self::ALBUMS_DIR).join(album_uid)
+    }
+}
+
+impl PhotoService for DirectoryPhotoService {
+    type Error = DirectoryServiceError;
+
+    async fn check_status(&self) -> Result<(), Self::Error> {
+        if !tokio::fs::metadata(&self.root).await?.is_dir() {
+            return Err(DirectoryServiceError::DirectoryNotFound(
+                self.root.display().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn check_access(&self) -> Result<(), Self::Error> {
+        self.check_status().await?;
+        tempfile::tempfile_in(&self.root)?;
+        Ok(())
+    }
+
+    async fn upload_photo<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+    ) -> Result<PhotoUID, Self::Error> {
+        let file_hash = calculate_sha1(&file_path).await?;
+        self.upload_photo_with_hash(file_path, &file_hash).await
+    }
+
+    /// The hash of the file is its UID.
+    async fn upload_photo_with_hash<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+        file_hash: &str,
+    ) -> Result<PhotoUID, Self::Error> {
+        debug_assert!(
+            file_hash.len() == 40 && file_hash.bytes().all(|b| b.is_ascii_hexdigit()),
+            "{} is not a SHA1 hash.",
+            file_hash
+        );
+        let photo_uid = PhotoUID(file_hash.to_owned());
+        if self.find_photo_by_hash(&photo_uid.0).await?.is_some() {
+            return Ok(photo_uid);
+        }
+
+        let extension = file_path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("unknown");
+        let dir = PathBuf::from(Utc::now().format(Self::DATE_DIR_FORMAT).to_string());
+        let relative_path = dir.join(format!("{}.{}", photo_uid.0, extension));
+        let path = self.root.join(&relative_path);
+        tokio::fs::create_dir_all(self.root.join(&dir)).await?;
+        // The file is copied, since it may belong to the local Telegram Bot API server.
+        tokio::fs::copy(&file_path, &path).await?;
+        Self::write_sidecar(&path, &Sidecar::default()).await?;
+        self.with_index(|index| index.insert(photo_uid.to_owned(), relative_path))
+            .await?;
+        Ok(photo_uid)
+    }
+
+    async fn find_photo_by_hash(&self, file_hash: &str) -> Result<Option<PhotoUID>, Self::Error> {
+        let photo_uid = PhotoUID(file_hash.to_owned());
+        Ok(self
+            .with_index(|index| index.contains_key(&photo_uid))
+            .await?
+            .then_some(photo_uid))
+    }
+
+    async fn add_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        let path = self.find_path(photo_uid).await?;
+        let mut sidecar = Self::read_sidecar(&path).await?;
+        if !sidecar.labels.iter().any(|l| l == label) {
+            sidecar.labels.push(label.to_owned());
+            Self::write_sidecar(&path, &sidecar).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        let path = self.find_path(photo_uid).await?;
+        let mut sidecar = Self::read_sidecar(&path).await?;
+        if sidecar.labels.iter().any(|l| l == label) {
+            sidecar.labels.retain(|l| l != label);
+            Self::write_sidecar(&path, &sidecar).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_photo_labels(&self, photo_uid: &PhotoUID) -> Result<Vec<String>, Self::Error> {
+        let path = self.find_path(photo_uid).await?;
+        Ok(Self::read_sidecar(&path).await?.labels)
+    }
+
+    /// The bot does not read EXIF, so only a date set by [`PhotoService::update_photo`] is known.
+    async fn has_capture_date(&self, photo_uid: &PhotoUID) -> Result<bool, Self::Error> {
+        let path = self.find_path(photo_uid).await?;
+        Ok(Self::read_sidecar(&path).await?.taken_at.is_some())
+    }
+
+    /// Moves the file to the directory of the date it has been taken at, if the date is given.
+    async fn update_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        details: &PhotoDetails,
+    ) -> Result<(), Self::Error> {
+        let mut path = self.find_path(photo_uid).await?;
+        let mut sidecar = Self::read_sidecar(&path).await?;
+        if let Some(title) = &details.title {
+            sidecar.title = Some(title.to_owned());
+        }
+        if let Some(description) = &details.description {
+            sidecar.description = Some(description.to_owned());
+        }
+        if let Some(taken_at) = details.taken_at {
+            sidecar.taken_at = Some(taken_at);
+            let dir = PathBuf::from(taken_at.format(Self::DATE_DIR_FORMAT).to_string());
+            let relative_path = self.move_photo(photo_uid, &dir).await?;
+            path = self.root.join(&relative_path);
+            self.with_index(|index| index.insert(photo_uid.to_owned(), relative_path))
+                .await?;
+        }
+        Self::write_sidecar(&path, &sidecar).await
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>, Self::Error> {
+        let mut albums = vec![];
+        let mut entries = match tokio::fs::read_dir(self.root.join(Self::ALBUMS_DIR)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(albums),
+            Err(err) => return Err(err.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                let title = entry.file_name().to_string_lossy().into_owned();
+                albums.push(Album {
+                    uid: title.clone(),
+                    title,
+                });
+            }
+        }
+        albums.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(albums)
+    }
+
+    /// Makes a directory in `Albums`. The title is the UID of the album.
+    async fn create_album(&self, title: &str) -> Result<Album, Self::Error> {
+        let title = title.trim();
+        if title.is_empty() || title.starts_with('.') || title.contains(['/', '\\']) {
+            return Err(DirectoryServiceError::InvalidAlbumTitle(title.to_owned()));
+        }
+        tokio::fs::create_dir_all(self.album_dir(title)).await?;
+        Ok(Album {
+            uid: title.to_owned(),
+            title: title.to_owned(),
+        })
+    }
+
+    async fn add_to_album(
+        &self,
+        album_uid: &str,
+        photo_uids: &[PhotoUID],
+    ) -> Result<(), Self::Error> {
+        let album_dir = self.album_dir(album_uid);
+        tokio::fs::create_dir_all(&album_dir).await?;
+        for photo_uid in photo_uids {
+            let path = self.find_path(photo_uid).await?;
+            let link_path = album_dir.join(path.file_name().unwrap_or_default());
+            if !tokio::fs::try_exists(&link_path).await? {
+                tokio::fs::hard_link(&path, link_path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the files to `Archive`, keeping their date directories.
+    async fn archive_photos(&self, photo_uids: &[PhotoUID]) -> Result<(), Self::Error> {
+        for photo_uid in photo_uids {
+            let relative_path = self
+                .with_index(|index| index.get(photo_uid).cloned())
+                .await?
+                .ok_or_else(|| DirectoryServiceError::PhotoNotFound(photo_uid.0.to_owned()))?;
+            let dir =
+                Path::new(Self::ARCHIVE_DIR).join(relative_path.parent().unwrap_or(Path::new("")));
+            self.move_photo(photo_uid, &dir).await?;
+            self.with_index(|index| index.remove(photo_uid)).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_labels(&self) -> Result<Vec<String>, Self::Error> {
+        let paths: Vec<PathBuf> = self
+            .with_index(|index| index.values().cloned().collect())
+            .await?;
+        let mut labels: Vec<String> = vec![];
+        for path in paths {
+            for label in Self::read_sidecar(&self.root.join(path)).await?.labels {
+                if !labels.contains(&label) {
+                    labels.push(label);
+                }
+            }
+        }
+        labels.sort();
+        Ok(labels)
+    }
+
+    /// The bot does not recognise faces, so the library knows no people.
+    async fn list_people(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(vec![])
+    }
+}
+
+/// Metadata of a file, kept in the XMP sidecar. Only the XMP written by the bot is parsed back.
+#[derive(Debug, Default)]
+struct Sidecar {
+    title: Option<String>,
+    description: Option<String>,
+    taken_at: Option<DateTime<Utc>>,
+    labels: Vec<String>,
+}
+
+impl Sidecar {
+    fn to_xmp(&self) -> String {
+        let mut properties = String::new();
+        if let Some(title) = &self.title {
+            properties.push_str(&format!(
+                "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+                escape_xml(title)
+            ));
+        }
+        if let Some(description) = &self.description {
+            properties.push_str(&format!(
+                "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+                escape_xml(description)
+            ));
+        }
+        if let Some(taken_at) = &self.taken_at {
+            properties.push_str(&format!(
+                "   <photoshop:DateCreated>{}</photoshop:DateCreated>\n",
+                taken_at.format("%Y-%m-%dT%H:%M:%SZ")
+            ));
+        }
+        if !self.labels.is_empty() {
+            properties.push_str("   <dc:subject><rdf:Bag>");
+            for label in &self.labels {
+                properties.push_str(&format!("<rdf:li>{}</rdf:li>", escape_xml(label)));
+            }
+            properties.push_str("</rdf:Bag></dc:subject>\n");
+        }
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/">
+{}  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#,
+            properties
+        )
+    }
+
+    fn parse(xmp: &str) -> Self {
+        Self {
+            title: element(xmp, "dc:title").and_then(|v| list_items(v).into_iter().next()),
+            description: element(xmp, "dc:description")
+                .and_then(|v| list_items(v).into_iter().next()),
+            taken_at: element(xmp, "photoshop:DateCreated")
+                .and_then(|v| DateTime::parse_from_rfc3339(v.trim()).ok())
+                .map(|v| v.with_timezone(&Utc)),
+            labels: element(xmp, "dc:subject")
+                .map(list_items)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// Returns the content of the first element with the name.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..end])
+}
+
+// Returns the unescaped values of the rdf:li elements.
+fn list_items(xml: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find("<rdf:li") {
+        rest = &rest[start..];
+        let Some(content_start) = rest.find('>') else {
+            break;
+        };
+        let Some(content_end) = rest.find("</rdf:li>") else {
+            break;
+        };
+        items.push(unescape_xml(&rest[content_start + 1..content_end]));
+        rest = &rest[content_end..];
+    }
+    items
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+        .replace("&amp;", "&")
+}
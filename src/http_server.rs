@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use log::{info, warn};
+use teloxide::prelude::*;
+
+use telegram2photoprism::{metrics, PhotoService};
+
+use crate::chats::Chats;
+use crate::Bot;
+
+// How long the readiness check waits for Telegram and the libraries.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct HttpServerState<S> {
+    bot: Bot,
+    chats: Arc<Chats<S>>,
+}
+
+// Derived Clone would require S: Clone.
+impl<S> Clone for HttpServerState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            bot: self.bot.clone(),
+            chats: self.chats.clone(),
+        }
+    }
+}
+
+/// Serves the Prometheus metrics at /metrics and the health checks at /healthz and /readyz.
+pub async fn run_http_server<S: PhotoService>(
+    address: SocketAddr,
+    bot: Bot,
+    chats: Arc<Chats<S>>,
+) -> Result<(), anyhow::Error> {
+    let router = Router::new()
+        .route("/metrics", get(encode_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz::<S>))
+        .with_state(HttpServerState { bot, chats });
+    info!("Serving metrics and health checks at http://{}", address);
+    axum::Server::try_bind(&address)?
+        .serve(router.into_make_service())
+        .await?;
+    Ok(())
+}
+
+/// Checks the readiness of the bot running with the given listen address.
+pub async fn check_readiness(address: SocketAddr) -> Result<(), anyhow::Error> {
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/readyz", address))
+        .timeout(READINESS_TIMEOUT * 2)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("The bot is not ready: {}", response.text().await?));
+    }
+    Ok(())
+}
+
+async fn encode_metrics() -> ([(axum::http::HeaderName, &'static str); 1], String) {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().encode(),
+    )
+}
+
+/// The process is alive as long as it responds.
+async fn healthz() -> &'static str {
+    "OK"
+}
+
+/// The bot is ready if it can reach Telegram and access every library.
+async fn readyz<S: PhotoService>(State(state): State<HttpServerState<S>>) -> (StatusCode, String) {
+    match tokio::time::timeout(READINESS_TIMEOUT, check_dependencies(&state)).await {
+        Ok(Ok(())) => (StatusCode::OK, "OK".to_owned()),
+        Ok(Err(err)) => {
+            warn!("Readiness check failed: {:?}", err);
+            (StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+        }
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Timed out after {:?}", READINESS_TIMEOUT),
+        ),
+    }
+}
+
+async fn check_dependencies<S: PhotoService>(
+    state: &HttpServerState<S>,
+) -> Result<(), anyhow::Error> {
+    state
+        .bot
+        .get_me()
+        .await
+        .map_err(|err| anyhow!("Telegram is not available: {}", err))?;
+    for photoservice in state.chats.photoservices() {
+        photoservice.check_access().await?;
+    }
+    Ok(())
+}
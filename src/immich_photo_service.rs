@@ -0,0 +1,388 @@
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::Utc;
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::{multipart, Body, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use crate::immich_photo_service::ImmichServiceError::{
+    RequestFailed, ServerNotAvailable, UploadFailed,
+};
+use crate::{Album, PhotoDetails, PhotoService, PhotoUID};
+
+#[derive(Error, Debug)]
+pub enum ImmichServiceError {
+    #[error("Failed to upload file {file} to Immich server: {details}.")]
+    UploadFailed { file: String, details: String },
+    #[error("Immich request {request} failed with {status}: {details}")]
+    RequestFailed {
+        request: String,
+        status: StatusCode,
+        details: String,
+    },
+    #[error("Immich server is not available: {0}")]
+    ServerNotAvailable(String),
+    #[error("Immich API Error: {}", .err.to_string())]
+    ImmichAPIError {
+        #[from]
+        err: reqwest::Error,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssetResponse {
+    #[serde(default)]
+    tags: Vec<TagResponse>,
+    exif_info: Option<ExifResponse>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExifResponse {
+    date_time_original: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TagResponse {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlbumResponse {
+    id: String,
+    album_name: String,
+}
+
+impl From<AlbumResponse> for Album {
+    fn from(album: AlbumResponse) -> Self {
+        Self {
+            uid: album.id,
+            title: album.album_name,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct BulkUploadCheckResponse {
+    results: Vec<BulkUploadCheckResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkUploadCheckResult {
+    asset_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PeopleResponse {
+    people: Vec<PersonResponse>,
+}
+
+#[derive(Deserialize)]
+struct PersonResponse {
+    name: String,
+}
+
+/// [Immich](https://immich.app) server, accessed with an API key.
+///
+/// Labels are Immich tags and the asset ID is the photo UID. Immich has no photo titles,
+/// so only the description of [`PhotoDetails`] is saved.
+pub struct ImmichPhotoService {
+    immich_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl ImmichPhotoService {
+    const DEVICE_ID: &'static str = "telegram2photoprism";
+
+    const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(immich_url: String, api_key: String) -> Self {
+        Self {
+            immich_url,
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn endpoint(&self, api_method: &str) -> String {
+        format!("{}/api{}", &self.immich_url, api_method)
+    }
+
+    fn request(&self, method: Method, api_method: &str) -> RequestBuilder {
+        self.client
+            .request(method, self.endpoint(api_method))
+            .header("x-api-key", &self.api_key)
+    }
+
+    /// Sends the request and returns the response if it is successful.
+    async fn send(
+        &self,
+        request_name: &str,
+        request_builder: RequestBuilder,
+    ) -> Result<Response, ImmichServiceError> {
+        let response = request_builder.send().await?;
+        if !response.status().is_success() {
+            return Err(RequestFailed {
+                request: request_name.to_owned(),
+                status: response.status(),
+                details: response.text().await?,
+            });
+        }
+        Ok(response)
+    }
+
+    async fn get_asset(&self, photo_uid: &PhotoUID) -> Result<AssetResponse, ImmichServiceError> {
+        let response = self
+            .send(
+                "get asset",
+                self.request(Method::GET, &format!("/assets/{}", photo_uid.0)),
+            )
+            .await?;
+        Ok(response.json::<AssetResponse>().await?)
+    }
+}
+
+impl PhotoService for ImmichPhotoService {
+    type Error = ImmichServiceError;
+
+    /// Checks that the Immich server responds. Unlike other requests, it is not authenticated.
+    async fn check_status(&self) -> Result<(), Self::Error> {
+        let ping_response = self
+            .client
+            .get(self.endpoint("/server/ping"))
+            .timeout(Self::STATUS_TIMEOUT)
+            .send()
+            .await?;
+
+        if ping_response.status() != StatusCode::OK {
+            return Err(ServerNotAvailable(ping_response.text().await?));
+        }
+        Ok(())
+    }
+
+    async fn check_access(&self) -> Result<(), Self::Error> {
+        self.send("get user", self.request(Method::GET, "/users/me"))
+            .await?;
+        Ok(())
+    }
+
+    async fn upload_photo<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+    ) -> Result<PhotoUID, Self::Error> {
+        let random_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 6);
+        let extension: &str = file_path
+            .as_ref()
+            .extension()
+            .and_then(|v| v.to_str())
+            .unwrap_or("unknown");
+        let file = File::open(&file_path).await.map_err(anyhow::Error::from)?;
+        let file_body: Body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+        // Immich takes the dates from EXIF if the file has them.
+        let now = Utc::now().to_rfc3339();
+        let form = multipart::Form::new()
+            .text("deviceAssetId", format!("telegram-{}", random_token))
+            .text("deviceId", Self::DEVICE_ID)
+            .text("fileCreatedAt", now.clone())
+            .text("fileModifiedAt", now)
+            .part(
+                "assetData",
+                multipart::Part::stream(file_body).file_name(format!("unknown.{}", extension)),
+            );
+        let upload_response = self
+            .request(Method::POST, "/assets")
+            .multipart(form)
+            .send()
+            .await?;
+
+        if !upload_response.status().is_success() {
+            return Err(UploadFailed {
+                file: file_path.as_ref().to_str().unwrap_or_default().to_owned(),
+                details: upload_response.text().await?,
+            });
+        }
+        let asset = upload_response.json::<UploadResponse>().await?;
+        Ok(PhotoUID(asset.id))
+    }
+
+    async fn find_photo_by_hash(&self, file_hash: &str) -> Result<Option<PhotoUID>, Self::Error> {
+        let check_params = json!({ "assets": [{ "id": file_hash, "checksum": file_hash }] });
+        let response = self
+            .send(
+                "check upload",
+                self.request(Method::POST, "/assets/bulk-upload-check")
+                    .json(&check_params),
+            )
+            .await?;
+        let check = response.json::<BulkUploadCheckResponse>().await?;
+        Ok(check
+            .results
+            .into_iter()
+            .find_map(|result| result.asset_id)
+            .map(PhotoUID))
+    }
+
+    async fn add_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        // Creates the tag if it does not exist yet.
+        let upsert_params = json!({ "tags": [label] });
+        let tags = self
+            .send(
+                "upsert tag",
+                self.request(Method::PUT, "/tags").json(&upsert_params),
+            )
+            .await?
+            .json::<Vec<TagResponse>>()
+            .await?;
+        let tag = tags
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Immich has not returned tag {}.", label))?;
+        let tag_assets_params = json!({ "ids": [photo_uid] });
+        self.send(
+            "tag asset",
+            self.request(Method::PUT, &format!("/tags/{}/assets", tag.id))
+                .json(&tag_assets_params),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        let asset = self.get_asset(photo_uid).await?;
+        let Some(tag) = asset.tags.iter().find(|tag| tag.name == label) else {
+            return Ok(());
+        };
+        let untag_assets_params = json!({ "ids": [photo_uid] });
+        self.send(
+            "untag asset",
+            self.request(Method::DELETE, &format!("/tags/{}/assets", tag.id))
+                .json(&untag_assets_params),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn list_photo_labels(&self, photo_uid: &PhotoUID) -> Result<Vec<String>, Self::Error> {
+        let asset = self.get_asset(photo_uid).await?;
+        Ok(asset.tags.into_iter().map(|tag| tag.name).collect())
+    }
+
+    async fn has_capture_date(&self, photo_uid: &PhotoUID) -> Result<bool, Self::Error> {
+        let asset = self.get_asset(photo_uid).await?;
+        Ok(asset
+            .exif_info
+            .is_some_and(|exif| exif.date_time_original.is_some()))
+    }
+
+    async fn update_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        details: &PhotoDetails,
+    ) -> Result<(), Self::Error> {
+        let mut update_params = serde_json::Map::new();
+        if let Some(description) = &details.description {
+            update_params.insert("description".to_owned(), description.as_str().into());
+        }
+        if let Some(taken_at) = &details.taken_at {
+            update_params.insert("dateTimeOriginal".to_owned(), taken_at.to_rfc3339().into());
+        }
+        if update_params.is_empty() {
+            return Ok(());
+        }
+        self.send(
+            "update asset",
+            self.request(Method::PUT, &format!("/assets/{}", photo_uid.0))
+                .json(&update_params),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>, Self::Error> {
+        let response = self
+            .send("list albums", self.request(Method::GET, "/albums"))
+            .await?;
+        let mut albums: Vec<Album> = response
+            .json::<Vec<AlbumResponse>>()
+            .await?
+            .into_iter()
+            .map(Album::from)
+            .collect();
+        albums.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(albums)
+    }
+
+    async fn create_album(&self, title: &str) -> Result<Album, Self::Error> {
+        let create_album_params = json!({ "albumName": title });
+        let response = self
+            .send(
+                "create album",
+                self.request(Method::POST, "/albums")
+                    .json(&create_album_params),
+            )
+            .await?;
+        Ok(response.json::<AlbumResponse>().await?.into())
+    }
+
+    async fn add_to_album(
+        &self,
+        album_uid: &str,
+        photo_uids: &[PhotoUID],
+    ) -> Result<(), Self::Error> {
+        let add_to_album_params = json!({ "ids": photo_uids });
+        self.send(
+            "add to album",
+            self.request(Method::PUT, &format!("/albums/{}/assets", album_uid))
+                .json(&add_to_album_params),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn archive_photos(&self, photo_uids: &[PhotoUID]) -> Result<(), Self::Error> {
+        let archive_params = json!({ "ids": photo_uids, "visibility": "archive" });
+        self.send(
+            "archive assets",
+            self.request(Method::PUT, "/assets").json(&archive_params),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn list_labels(&self) -> Result<Vec<String>, Self::Error> {
+        let response = self
+            .send("list tags", self.request(Method::GET, "/tags"))
+            .await?;
+        let tags = response.json::<Vec<TagResponse>>().await?;
+        Ok(tags.into_iter().map(|tag| tag.name).collect())
+    }
+
+    async fn list_people(&self) -> Result<Vec<String>, Self::Error> {
+        let response = self
+            .send("list people", self.request(Method::GET, "/people"))
+            .await?;
+        let people = response.json::<PeopleResponse>().await?;
+        Ok(people
+            .people
+            .into_iter()
+            .map(|person| person.name)
+            .filter(|name| !name.is_empty())
+            .collect())
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use chrono::Utc;
+use log::{info, warn};
+use teloxide::types::ChatId;
+
+use telegram2photoprism::{calculate_sha1, read_chat_export, PhotoService, UploadRecord};
+
+use crate::chats::Chats;
+use crate::{
+    apply_caption, apply_message_date, find_or_create_album, ApplicationContext, Caption,
+    ImportArgs,
+};
+
+/// Uploads the photos and videos of a Telegram Desktop chat export.
+/// Every imported message is recorded in the message store, so an interrupted import
+/// continues from the first message which has not been imported yet.
+pub async fn import_chat_export<S: PhotoService>(
+    import_args: &ImportArgs,
+    app_context: &ApplicationContext,
+    chats: &Chats<S>,
+) -> Result<(), anyhow::Error> {
+    let chat = match import_args.chat_id {
+        Some(chat_id) => chats
+            .get(ChatId(chat_id))
+            .ok_or_else(|| anyhow!("Chat {} is not watched by the bot.", chat_id))?,
+        None => chats.single().ok_or_else(|| {
+            anyhow!(
+                "The bot watches several chats. Use --chat-id to choose the chat of the export."
+            )
+        })?,
+    };
+    let photoservice = chat.photoservice.as_ref();
+    let album = match &chat.album {
+        Some(album_title) => Some(find_or_create_album(photoservice, album_title).await?),
+        None => None,
+    };
+
+    let messages = read_chat_export(&import_args.export_dir).await?;
+    let mut imported_count = 0;
+    let mut skipped_count = 0;
+    for (index, message) in messages.iter().enumerate() {
+        if app_context
+            .message_store
+            .find_by_message(chat.id.0, message.id)
+            .await
+            .is_some()
+        {
+            skipped_count += 1;
+            continue;
+        }
+        let path = import_args.export_dir.join(&message.file);
+        if !path.is_file() {
+            // Telegram Desktop leaves a placeholder instead of files which exceed the export size limit.
+            warn!(
+                "File {} of message {} is not included in the export.",
+                path.display(),
+                message.id
+            );
+            skipped_count += 1;
+            continue;
+        }
+
+        let sha1 = calculate_sha1(&path).await?;
+        let photo_uid = match photoservice.find_photo_by_hash(&sha1).await? {
+            Some(photo_uid) => photo_uid,
+            None => photoservice.upload_photo_with_hash(&path, &sha1).await?,
+        };
+        let caption = Caption::new(&message.text, message.hashtags.clone());
+        apply_caption(app_context, photoservice, &photo_uid, &caption).await?;
+        if let Some(date) = message.date {
+            apply_message_date(photoservice, &photo_uid, date).await?;
+        }
+        for label in &chat.labels {
+            photoservice.add_label(&photo_uid, label).await?;
+        }
+        if let Some(album) = &album {
+            photoservice
+                .add_to_album(&album.uid, std::slice::from_ref(&photo_uid))
+                .await?;
+        }
+        app_context
+            .message_store
+            .insert(UploadRecord {
+                chat_id: chat.id.0,
+                message_id: message.id,
+                status_message_id: None,
+                file_unique_id: message.file.display().to_string(),
+                sha1,
+                photo_uid,
+                uploaded_at: Utc::now(),
+                labels: chat
+                    .labels
+                    .iter()
+                    .chain(message.hashtags.iter())
+                    .cloned()
+                    .collect(),
+            })
+            .await?;
+        imported_count += 1;
+        info!(
+            "Imported message {} ({}/{}).",
+            message.id,
+            index + 1,
+            messages.len()
+        );
+    }
+
+    info!(
+        "Imported {} files, skipped {} files which have been imported before or are not included in the export.",
+        imported_count, skipped_count
+    );
+    Ok(())
+}
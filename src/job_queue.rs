@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+/// Job waiting in a [`JobQueue`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job<T> {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    /// Number of failed attempts.
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub payload: T,
+}
+
+#[derive(Error, Debug)]
+pub enum JobQueueError {
+    #[error("Failed to access job queue: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Job queue {file} is corrupted at line {line}: {err}")]
+    Corrupted {
+        file: String,
+        line: usize,
+        err: serde_json::Error,
+    },
+}
+
+// Line of the journal. A job without a body has been finished.
+#[derive(Serialize, Deserialize)]
+struct JournalEntry<T> {
+    id: u64,
+    job: Option<Job<T>>,
+}
+
+/// Persistent queue of jobs which are retried until they succeed.
+///
+/// Jobs are kept in memory and appended to a JSON lines journal on every change.
+/// The latest line of a job wins, so the journal is compacted every time the queue is opened.
+pub struct JobQueue<T> {
+    state: Mutex<JobQueueState<T>>,
+}
+
+struct JobQueueState<T> {
+    jobs: BTreeMap<u64, Job<T>>,
+    next_id: u64,
+    journal: File,
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> JobQueue<T> {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, JobQueueError> {
+        let path = path.as_ref();
+        let jobs = Self::read_journal(path).await?;
+
+        // Rewrite the journal with the latest version of the unfinished jobs only.
+        let compacted_path = path.with_extension("compacted");
+        let mut compacted = File::create(&compacted_path).await?;
+        for job in jobs.values() {
+            compacted
+                .write_all(&Self::journal_line(job.id, Some(job)))
+                .await?;
+        }
+        compacted.sync_all().await?;
+        tokio::fs::rename(&compacted_path, path).await?;
+
+        let journal = OpenOptions::new().append(true).open(path).await?;
+        let next_id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        Ok(Self {
+            state: Mutex::new(JobQueueState {
+                jobs,
+                next_id,
+                journal,
+            }),
+        })
+    }
+
+    async fn read_journal(path: &Path) -> Result<BTreeMap<u64, Job<T>>, JobQueueError> {
+        let mut jobs = BTreeMap::new();
+        let file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(jobs),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut lines = BufReader::new(file).lines();
+        let mut line_number = 0;
+        let mut next_line = lines.next_line().await?;
+        while let Some(line) = next_line {
+            line_number += 1;
+            next_line = lines.next_line().await?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: JournalEntry<T> = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                // A crash while the entry was written leaves it incomplete at the end of the journal.
+                Err(err) if next_line.is_none() => {
+                    warn!(
+                        "Skipping incomplete line {} of {}: {}",
+                        line_number,
+                        path.display(),
+                        err
+                    );
+                    break;
+                }
+                Err(err) => {
+                    return Err(JobQueueError::Corrupted {
+                        file: path.display().to_string(),
+                        line: line_number,
+                        err,
+                    })
+                }
+            };
+            match entry.job {
+                Some(job) => jobs.insert(entry.id, job),
+                None => jobs.remove(&entry.id),
+            };
+        }
+        Ok(jobs)
+    }
+
+    fn journal_line(id: u64, job: Option<&Job<T>>) -> Vec<u8> {
+        let mut line = serde_json::to_vec(&JournalEntry {
+            id,
+            job: job.cloned(),
+        })
+        .unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    async fn write(state: &mut JobQueueState<T>, id: u64) -> Result<(), JobQueueError> {
+        let line = Self::journal_line(id, state.jobs.get(&id));
+        state.journal.write_all(&line).await?;
+        state.journal.flush().await?;
+        Ok(())
+    }
+
+    /// Adds a job which is due immediately.
+    pub async fn push(&self, payload: T) -> Result<Job<T>, JobQueueError> {
+        let mut state = self.state.lock().await;
+        let now = Utc::now();
+        let job = Job {
+            id: state.next_id,
+            created_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            payload,
+        };
+        state.next_id += 1;
+        state.jobs.insert(job.id, job.clone());
+        Self::write(&mut state, job.id).await?;
+        Ok(job)
+    }
+
+    /// Returns the job which has been waiting for its attempt the longest, if any is due.
+    pub async fn next_due(&self, now: DateTime<Utc>) -> Option<Job<T>> {
+        let state = self.state.lock().await;
+        state
+            .jobs
+            .values()
+            .filter(|job| job.next_attempt_at <= now)
+            .min_by_key(|job| job.next_attempt_at)
+            .cloned()
+    }
+
+    /// Returns the time of the earliest attempt of the queued jobs.
+    pub async fn next_attempt_at(&self) -> Option<DateTime<Utc>> {
+        let state = self.state.lock().await;
+        state.jobs.values().map(|job| job.next_attempt_at).min()
+    }
+
+    /// Records a failed attempt of the job and schedules the next one.
+    pub async fn postpone(
+        &self,
+        id: u64,
+        next_attempt_at: DateTime<Utc>,
+        error: String,
+    ) -> Result<Option<Job<T>>, JobQueueError> {
+        let mut state = self.state.lock().await;
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return Ok(None);
+        };
+        job.attempts += 1;
+        job.next_attempt_at = next_attempt_at;
+        job.last_error = Some(error);
+        let job = job.clone();
+        Self::write(&mut state, id).await?;
+        Ok(Some(job))
+    }
+
+    /// Moves the next attempt of the job. Returns false if the job is not in the queue.
+    pub async fn reschedule(
+        &self,
+        id: u64,
+        next_attempt_at: DateTime<Utc>,
+    ) -> Result<bool, JobQueueError> {
+        let mut state = self.state.lock().await;
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return Ok(false);
+        };
+        job.next_attempt_at = next_attempt_at;
+        Self::write(&mut state, id).await?;
+        Ok(true)
+    }
+
+    /// Removes the job after it has succeeded or has been given up.
+    pub async fn remove(&self, id: u64) -> Result<(), JobQueueError> {
+        let mut state = self.state.lock().await;
+        if state.jobs.remove(&id).is_some() {
+            Self::write(&mut state, id).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn jobs(&self) -> Vec<Job<T>> {
+        let state = self.state.lock().await;
+        state.jobs.values().cloned().collect()
+    }
+}
@@ -1,4 +1,19 @@
-#![allow(async_fn_in_trait)]
+pub use chat_args::*;
+pub use chat_export::*;
+pub use composite_photo_service::*;
+pub use directory_photo_service::*;
+pub use immich_photo_service::*;
+pub use job_queue::*;
+pub use message_store::*;
+pub use metrics::*;
 pub use photo_service::*;
 
+mod chat_args;
+mod chat_export;
+mod composite_photo_service;
+mod directory_photo_service;
+mod immich_photo_service;
+mod job_queue;
+mod message_store;
+mod metrics;
 mod photo_service;
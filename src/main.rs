@@ -1,42 +1,215 @@
 #[macro_use]
 extern crate rust_i18n;
 
+use std::collections::HashMap;
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::anyhow;
-use clap::{arg, Parser};
-use log::{debug, info};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use clap::builder::TypedValueParser;
+use clap::error::ErrorKind;
+use clap::{Parser, Subcommand};
+use futures_util::{StreamExt, TryStreamExt};
+use log::{debug, error, info, warn, LevelFilter};
+use moka::future::Cache;
 use rust_i18n::i18n;
 use serde::{Deserialize, Serialize};
 use teloxide::adaptors::throttle::Limits;
 use teloxide::adaptors::Throttle;
 use teloxide::net::Download;
 use teloxide::prelude::*;
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::types::{
+    FileMeta, ForceReply, InlineKeyboardButton, InlineKeyboardMarkup, MessageEntityKind, MessageId,
+};
+use teloxide::update_listeners::webhooks;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio_util::codec::{BytesCodec, FramedRead};
 
-use telegram2photoprism::PhotoService;
-use telegram2photoprism::{PhotoPrismPhotoService, PhotoUID};
+use telegram2photoprism::{calculate_sha1, metrics, FileStream, PhotoService, StreamHasher};
+use telegram2photoprism::{
+    Album, ChatArgs, JobQueue, MessageStore, PhotoDetails, PhotoUID, UploadRecord,
+};
+
+use crate::chats::{
+    composite_services, directory_services, immich_services, photoprism_services, Backend,
+    ChatContext, Chats, TagSource,
+};
+use crate::commands::{handle_command, register_commands, ChatCommand};
+
+mod chats;
+mod commands;
+mod config;
+mod http_server;
+mod import;
+mod upload_worker;
 
 i18n!("resources/locales", fallback = "en");
 
 const UNKNOWN_EXTENSION: &str = "unknown";
 
+const MESSAGE_STORE_FILE_NAME: &str = "messages.jsonl";
+
+const UPLOAD_QUEUE_FILE_NAME: &str = "uploads.jsonl";
+
+// How long the bot remembers the tags selected on a tag keyboard which has not been saved yet,
+// and the albums of an album keyboard.
+const TAG_KEYBOARD_STATE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// How long the bot waits for the name of a new album.
+const ALBUM_NAME_PROMPT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeyboardData {
+    Tags(TagKeyboardData),
+    Album(AlbumKeyboardData),
+}
+
+/// Button of a tag keyboard. Telegram limits callback data to 64 bytes,
+/// so the selected tags are kept on the server in [`TagKeyboardState`].
 #[derive(Serialize, Deserialize)]
 struct TagKeyboardData {
     id: i32,
-    values: Vec<i32>,
-    photo_uid: String,
 }
 
 impl TagKeyboardData {
     const SAVE_BUTTON_ID: i32 = -2;
 
+    const PREVIOUS_PAGE_BUTTON_ID: i32 = -3;
+
+    const NEXT_PAGE_BUTTON_ID: i32 = -4;
+
+    const TAGS_PER_ROW: usize = 3;
+
     const CHECK_MARK_SYMBOL: char = '\u{2713}';
 }
 
+#[derive(Clone)]
+struct TagKeyboardState {
+    // Tags the keyboard has been made of. Tags taken from PhotoPrism may change meanwhile.
+    tags: Arc<Vec<String>>,
+    selected: Vec<usize>,
+    // Tags the files already had when the keyboard was opened. Deselecting them removes the labels.
+    saved: Vec<usize>,
+    page: usize,
+}
+
+impl TagKeyboardState {
+    fn new(tags: Arc<Vec<String>>) -> Self {
+        Self {
+            tags,
+            selected: vec![],
+            saved: vec![],
+            page: 0,
+        }
+    }
+
+    /// Makes a keyboard with the labels the files already have selected.
+    /// Labels which are not among the tags are added to the end of the keyboard.
+    fn with_labels(tags: Arc<Vec<String>>, labels: &[String]) -> Self {
+        let mut all_tags = tags.as_ref().clone();
+        for label in labels {
+            if !all_tags.contains(label) {
+                all_tags.push(label.to_owned());
+            }
+        }
+        let saved: Vec<usize> = labels
+            .iter()
+            .filter_map(|label| all_tags.iter().position(|tag| tag == label))
+            .collect();
+        Self {
+            tags: Arc::new(all_tags),
+            selected: saved.clone(),
+            saved,
+            page: 0,
+        }
+    }
+
+    fn tags_of(&self, indexes: impl Iterator<Item = usize>) -> Vec<String> {
+        indexes
+            .filter_map(|index| self.tags.get(index))
+            .cloned()
+            .collect()
+    }
+
+    fn page_count(&self, page_size: usize) -> usize {
+        self.tags.len().div_ceil(page_size)
+    }
+
+    fn toggle(&mut self, tag_index: usize) {
+        match self.selected.iter().position(|&x| x == tag_index) {
+            Some(pos) => {
+                self.selected.remove(pos);
+            }
+            None => self.selected.push(tag_index),
+        }
+    }
+}
+
+/// Button of an album keyboard. Album UIDs may not fit into the 64 bytes of callback data,
+/// so the albums are kept on the server in [`AlbumKeyboardState`].
+#[derive(Serialize, Deserialize)]
+struct AlbumKeyboardData {
+    album: i32,
+}
+
+impl AlbumKeyboardData {
+    const NEW_ALBUM_BUTTON_ID: i32 = -2;
+
+    const PREVIOUS_PAGE_BUTTON_ID: i32 = -3;
+
+    const NEXT_PAGE_BUTTON_ID: i32 = -4;
+
+    const ALBUMS_PER_ROW: usize = 2;
+
+    // Telegram does not show keyboards with more than 100 buttons.
+    const ALBUMS_PER_PAGE: usize = 20;
+}
+
+#[derive(Clone)]
+struct AlbumKeyboardState {
+    // Albums the keyboard has been made of.
+    albums: Arc<Vec<Album>>,
+    page: usize,
+}
+
+impl AlbumKeyboardState {
+    fn new(albums: Vec<Album>) -> Self {
+        Self {
+            albums: Arc::new(albums),
+            page: 0,
+        }
+    }
+
+    fn page_count(&self) -> usize {
+        self.albums
+            .len()
+            .div_ceil(AlbumKeyboardData::ALBUMS_PER_PAGE)
+    }
+}
+
+/// Files of one or several messages (a media group) which should be uploaded.
+#[derive(Clone, Serialize, Deserialize)]
+struct UploadTask {
+    chat_id: i64,
+    /// Message of the bot which reports the upload status.
+    status_message_id: i32,
+    messages: Vec<Message>,
+}
+
+/// Prompt for the name of a new album which the files of the status message should be added to.
+#[derive(Clone)]
+struct AlbumNamePrompt {
+    status_message_id: MessageId,
+}
+
 type Bot = Throttle<teloxide::Bot>;
 
 /// telegram2photoprism is a bot that downloads images and videos from a Telegram channel and uploads them to a PhotoPrism server.
@@ -44,12 +217,28 @@ type Bot = Throttle<teloxide::Bot>;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file with the options. Options given on the command line or by environment variables take precedence.
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_CONFIG")]
+    config: Option<PathBuf>,
     /// telegram bot access token
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_TELEGRAM_ACCESS_TOKEN")]
-    telegram_access_token: String,
+    telegram_access_token: Option<String>,
     /// telegram chat id from where photo will be downloaded and uploaded to PhotoPrism server.
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_TELEGRAM_CHAT_ID")]
-    telegram_chat_id: i64,
+    telegram_chat_id: Option<i64>,
+    /// Another chat to watch, with its own settings:
+    /// id=<chat id>,tags=<tag>|<tag>,album=<album>,labels=<label>|<label>,photoprism-url=<url>,photoprism-username=<username>,photoprism-password=<password>,
+    /// immich-url=<url>,immich-api-key=<key>.
+    /// Only id is required, the rest is taken from the global options. Can be given several times.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_CHATS",
+        value_delimiter = ';',
+        value_parser = ChatArgsParser
+    )]
+    chat: Vec<ChatArgs>,
     /// Telegram bot API server. For more information, visit https://github.com/tdlib/telegram-bot-api
     #[arg(
         long,
@@ -60,15 +249,50 @@ struct Args {
     /// Tags from which the user will choose tags for the photo.
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_TAGS", value_delimiter = ',')]
     tags: Vec<String>,
+    /// Where the tags of the tag keyboard come from. Several sources can be combined.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_TAGS_SOURCE",
+        value_delimiter = ',',
+        default_value = "static"
+    )]
+    tags_source: Vec<TagSource>,
+    /// Number of seconds the labels and people fetched from PhotoPrism are cached for.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_TAGS_CACHE_TTL_SEC",
+        default_value_t = 300
+    )]
+    tags_cache_ttl_sec: u64,
+    /// Libraries the files are uploaded to. With several of them every file is uploaded to each one,
+    /// e.g. photoprism,directory keeps a backup of the files in a directory.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_BACKEND",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "photoprism"
+    )]
+    backend: Vec<Backend>,
+    /// Immich URL, used with --backend immich
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_IMMICH_URL")]
+    immich_url: Option<String>,
+    /// Immich API key, created in the account settings of the Immich user the files are uploaded as
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_IMMICH_API_KEY")]
+    immich_api_key: Option<String>,
+    /// Directory the files are stored in with --backend directory, organised by date: <year>/<month>/<sha1>.<extension>.
+    /// Labels, caption and date of every file are written to an XMP sidecar next to it.
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_DIRECTORY")]
+    directory: Option<PathBuf>,
     /// PhotoPrism URL
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_PHOTOPRISM_URL")]
-    photoprism_url: String,
+    photoprism_url: Option<String>,
     /// PhotoPrism username
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_PHOTOPRISM_USERNAME")]
-    photoprism_username: String,
+    photoprism_username: Option<String>,
     /// PhotoPrism password
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_PHOTOPRISM_PASSWORD")]
-    photoprism_password: String,
+    photoprism_password: Option<String>,
     /// Number of seconds after which the bot should obtain a new X-Auth-Token using the username and password.
     /// Should be less than PHOTOPRISM_SESSION_TIMEOUT (https://docs.photoprism.app/getting-started/config-options/)
     #[arg(
@@ -80,7 +304,7 @@ struct Args {
     /// Locale
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_LOCALE", value_parser = ["en", "ru"], default_value = "en")]
     locale: String,
-    /// Directory for temporary downloaded files
+    /// Directory for temporary downloaded files and the bot state
     #[arg(long, env = "TELEGRAM2PHOTOPRISM_WORKING_DIR", default_value_os_t = Self::get_default_working_dir())]
     working_dir: PathBuf,
     /// By default, Telegram compresses videos and images if they are not attached as files.
@@ -92,6 +316,124 @@ struct Args {
         default_value_t = false
     )]
     disallow_compressed_files: bool,
+    /// Number of milliseconds the bot waits for the rest of a Telegram album (media group)
+    /// before uploading all of its files as one batch.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_MEDIA_GROUP_TIMEOUT_MS",
+        default_value_t = 1000
+    )]
+    media_group_timeout_ms: u64,
+    /// Use the first line of the message caption (without hashtags) as the photo title.
+    /// The caption is always saved as the photo description and its hashtags as labels.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_CAPTION_AS_TITLE",
+        default_value_t = false
+    )]
+    caption_as_title: bool,
+    /// Number of tags on one page of the tag keyboard.
+    /// Tags which do not fit are shown on the next pages.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_TAGS_PAGE_SIZE",
+        default_value_t = 12,
+        value_parser = clap::value_parser!(u16).range(1..=90)
+    )]
+    tags_page_size: u16,
+    /// After upload, offer to add the files to one of the existing PhotoPrism albums or to a new one.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_CHOOSE_ALBUM",
+        default_value_t = false
+    )]
+    choose_album: bool,
+    /// Allow chat admins to delete a photo or video with the /delete command replying to it.
+    /// The message is deleted from the chat and the file is moved to the PhotoPrism archive.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_ARCHIVE_ON_DELETE",
+        default_value_t = false
+    )]
+    archive_on_delete: bool,
+    /// Upload the files to PhotoPrism while they are downloaded from Telegram, without storing them
+    /// in the working directory. The hash of a file is then known only after the upload, so files which
+    /// PhotoPrism already has are uploaded again and skipped by PhotoPrism. Other backends ignore it.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_STREAM_UPLOADS",
+        default_value_t = false
+    )]
+    stream_uploads: bool,
+    /// Address of the HTTP server exposing Prometheus metrics at /metrics and the health checks
+    /// at /healthz and /readyz, e.g. 0.0.0.0:9090. The server is not started if the address is not given.
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_LISTEN_ADDRESS")]
+    listen_address: Option<SocketAddr>,
+    /// Public HTTPS URL Telegram sends updates to, e.g. https://example.com/telegram2photoprism.
+    /// If it is given, the bot receives updates by a webhook instead of long polling.
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_WEBHOOK_URL")]
+    webhook_url: Option<String>,
+    /// Address the webhook server listens at. The reverse proxy should forward the webhook URL to it.
+    #[arg(
+        long,
+        env = "TELEGRAM2PHOTOPRISM_WEBHOOK_LISTEN",
+        default_value = "0.0.0.0:8443"
+    )]
+    webhook_listen: SocketAddr,
+    /// Secret token Telegram sends with every webhook request, so requests of others are rejected.
+    /// 1-256 characters: A-Z, a-z, 0-9, _ and -. A random token is used if it is not given.
+    #[arg(long, env = "TELEGRAM2PHOTOPRISM_WEBHOOK_SECRET_TOKEN")]
+    webhook_secret_token: Option<String>,
+}
+
+/// Parses --chat. Unlike the default parser, errors do not repeat the value,
+/// since it may include a password or an API key.
+#[derive(Clone)]
+struct ChatArgsParser;
+
+impl TypedValueParser for ChatArgsParser {
+    type Value = ChatArgs;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let arg = arg.map_or_else(|| "--chat".to_owned(), |arg| arg.to_string());
+        let invalid_value = |err: anyhow::Error| {
+            clap::Error::raw(
+                ErrorKind::ValueValidation,
+                format!("invalid value for '{}': {}\n", arg, err),
+            )
+            .with_cmd(cmd)
+        };
+        value
+            .to_str()
+            .ok_or_else(|| invalid_value(anyhow!("The chat settings are not valid UTF-8.")))?
+            .parse()
+            .map_err(invalid_value)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Upload the photos and videos of a Telegram Desktop chat export ("Export chat history" in JSON format)
+    /// instead of running the bot. Files which have already been imported are skipped, so an interrupted import
+    /// can be started again.
+    Import(ImportArgs),
+    /// Check that the running bot is ready, using its /readyz endpoint at --listen-address.
+    /// Exits with an error if it is not, so it can be used as a container health check.
+    Healthcheck,
+}
+
+#[derive(clap::Args, Debug)]
+struct ImportArgs {
+    /// Folder of the export with result.json
+    export_dir: PathBuf,
+    /// Chat the export has been made of. Required if the bot watches several chats.
+    #[arg(long)]
+    chat_id: Option<i64>,
 }
 
 impl Args {
@@ -103,136 +445,627 @@ impl Args {
 struct ApplicationContext {
     working_dir: OsString,
     disallow_compressed_files: bool,
-    tags: Vec<String>,
+    tags_page_size: usize,
+    caption_as_title: bool,
+    choose_album: bool,
+    archive_on_delete: bool,
+    stream_uploads: bool,
+    media_group_timeout: Duration,
+    // Messages of Telegram albums which are still being collected, by media_group_id.
+    media_groups: Mutex<HashMap<String, Vec<Message>>>,
+    message_store: MessageStore,
+    upload_queue: JobQueue<UploadTask>,
+    // Wakes up the upload worker when a job is added to the queue.
+    upload_queue_changed: Notify,
+    tag_keyboards: Cache<(ChatId, MessageId), TagKeyboardState>,
+    album_keyboards: Cache<(ChatId, MessageId), AlbumKeyboardState>,
+    // Status messages of the uploads which are waiting for a new album name, by prompt message.
+    album_name_prompts: Cache<(ChatId, MessageId), MessageId>,
 }
 
 impl ApplicationContext {
-    pub fn new(args: &Args) -> Self {
+    pub fn new(
+        args: &Args,
+        message_store: MessageStore,
+        upload_queue: JobQueue<UploadTask>,
+    ) -> Self {
         Self {
-            tags: args.tags.clone(),
             working_dir: args.working_dir.clone().into_os_string(),
             disallow_compressed_files: args.disallow_compressed_files,
+            tags_page_size: args.tags_page_size.into(),
+            caption_as_title: args.caption_as_title,
+            choose_album: args.choose_album,
+            archive_on_delete: args.archive_on_delete,
+            stream_uploads: args.stream_uploads,
+            media_group_timeout: Duration::from_millis(args.media_group_timeout_ms),
+            media_groups: Mutex::new(HashMap::new()),
+            message_store,
+            upload_queue,
+            upload_queue_changed: Notify::new(),
+            tag_keyboards: Cache::builder()
+                .time_to_live(TAG_KEYBOARD_STATE_TTL)
+                .build(),
+            album_keyboards: Cache::builder()
+                .time_to_live(TAG_KEYBOARD_STATE_TTL)
+                .build(),
+            album_name_prompts: Cache::builder().time_to_live(ALBUM_NAME_PROMPT_TTL).build(),
         }
     }
+
+    /// Adds the message to its media group. Returns true if it is the first message of the group.
+    fn add_to_media_group(&self, media_group_id: &str, m: Message) -> bool {
+        let mut media_groups = self.media_groups.lock().unwrap();
+        let messages = media_groups.entry(media_group_id.to_owned()).or_default();
+        messages.push(m);
+        messages.len() == 1
+    }
+
+    fn take_media_group(&self, media_group_id: &str) -> Vec<Message> {
+        let mut media_groups = self.media_groups.lock().unwrap();
+        let mut messages = media_groups.remove(media_group_id).unwrap_or_default();
+        messages.sort_by_key(|m| m.id.0);
+        messages
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    pretty_env_logger::init();
-    let args = Args::parse();
+    let args = Args::load()?;
+    let mut logger = pretty_env_logger::formatted_builder();
+    // The import reports its progress in the log, so it is shown unless RUST_LOG says otherwise.
+    if let Some(Command::Import(_)) = &args.command {
+        logger.filter_module(module_path!(), LevelFilter::Info);
+    }
+    if let Ok(filters) = env::var("RUST_LOG") {
+        logger.parse_filters(&filters);
+    }
+    logger.init();
+
+    if let Some(Command::Healthcheck) = &args.command {
+        let listen_address = args
+            .listen_address
+            .ok_or_else(|| anyhow!("The health check requires --listen-address."))?;
+        return http_server::check_readiness(listen_address).await;
+    }
 
     rust_i18n::set_locale(&args.locale);
-    let context = ApplicationContext::new(&args);
-    let chat_id = ChatId(args.telegram_chat_id);
-    let bot: Bot = teloxide::Bot::new(args.telegram_access_token)
+    let message_store = MessageStore::open(args.working_dir.join(MESSAGE_STORE_FILE_NAME)).await?;
+    let upload_queue = JobQueue::open(args.working_dir.join(UPLOAD_QUEUE_FILE_NAME)).await?;
+    let context = Arc::new(ApplicationContext::new(&args, message_store, upload_queue));
+    match args.backend.as_slice() {
+        [Backend::PhotoPrism] => {
+            let chats = Chats::new(&args, photoprism_services(&args))?;
+            run(&args, context, chats).await
+        }
+        [Backend::Immich] => {
+            let chats = Chats::new(&args, immich_services(&args))?;
+            run(&args, context, chats).await
+        }
+        [Backend::Directory] => {
+            let chats = Chats::new(&args, directory_services(&args))?;
+            run(&args, context, chats).await
+        }
+        _ => {
+            let chats = Chats::new(&args, composite_services(&args)?)?;
+            run(&args, context, chats).await
+        }
+    }
+}
+
+/// Runs the command or the bot with the chats uploading to the chosen backend.
+async fn run<S: PhotoService>(
+    args: &Args,
+    context: Arc<ApplicationContext>,
+    chats: Chats<S>,
+) -> Result<(), anyhow::Error> {
+    let chats = Arc::new(chats);
+    if let Some(Command::Import(import_args)) = &args.command {
+        return import::import_chat_export(import_args, &context, &chats).await;
+    }
+    let telegram_access_token = args.telegram_access_token.clone().ok_or_else(|| {
+        anyhow!("Telegram access token is not given. Use --telegram-access-token or telegram-access-token in the config file.")
+    })?;
+    let bot: Bot = teloxide::Bot::new(telegram_access_token)
         .set_api_url(reqwest::Url::parse(&args.telegram_bot_api_server)?)
         .throttle(Limits::default());
 
-    let photoservice = PhotoPrismPhotoService::new(
-        args.photoprism_url,
-        args.photoprism_username,
-        args.photoprism_password,
-        args.photoprism_session_refresh_sec,
-    );
-
     let handler = dptree::entry()
         .branch(
             Update::filter_message()
-                .filter(move |m: Message| m.chat.id == chat_id)
-                .endpoint(handle_media_message_with_error),
+                .filter_map(|m: Message, chats: Arc<Chats<S>>| chats.get(m.chat.id))
+                .branch(
+                    dptree::entry()
+                        .filter_command::<ChatCommand>()
+                        .endpoint(handle_command_with_error::<S>),
+                )
+                .branch(
+                    dptree::filter_map_async(find_album_name_prompt)
+                        .endpoint(handle_album_name_message_with_error::<S>),
+                )
+                .branch(
+                    dptree::filter(|m: Message| get_file(&m).is_some())
+                        .endpoint(handle_media_message_with_error),
+                ),
         )
         .branch(
             Update::filter_callback_query()
-                .filter(move |callback: CallbackQuery| match callback.message {
-                    Some(msg) => msg.chat.id == chat_id,
-                    None => false,
+                .filter_map(|callback: CallbackQuery, chats: Arc<Chats<S>>| {
+                    callback.message.and_then(|msg| chats.get(msg.chat.id))
                 })
-                .endpoint(handle_callback_message_with_error),
+                .endpoint(handle_callback_message_with_error::<S>),
         );
 
     info!("Starting telegram2photoprism bot...");
+    if let Some(listen_address) = args.listen_address {
+        let (bot, chats) = (bot.clone(), chats.clone());
+        tokio::spawn(async move {
+            if let Err(err) = http_server::run_http_server(listen_address, bot, chats).await {
+                error!("HTTP server failed: {:?}", err);
+            }
+        });
+    }
+    // The commands only fill the menu of Telegram clients, so the bot works without them.
+    if let Err(err) = register_commands(&bot, &context).await {
+        warn!("Failed to register the bot commands: {}", err);
+    }
+    tokio::spawn(upload_worker::run_upload_worker(
+        bot.clone(),
+        context.clone(),
+        chats.clone(),
+    ));
 
+    let webhook_options = webhook_options(args)?;
     // Create a dispatcher for our bot
-    Dispatcher::builder(bot, handler)
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .distribution_function(|x| Some(x.id))
-        .dependencies(dptree::deps![Arc::new(context), Arc::new(photoservice)])
+        .dependencies(dptree::deps![context, chats])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+    match webhook_options {
+        Some(options) => {
+            info!("Receiving updates by webhook at {}", options.url);
+            let listener = webhooks::axum(bot, options).await?;
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("An error from the webhook listener"),
+                )
+                .await;
+        }
+        None => dispatcher.dispatch().await,
+    }
 
     Ok(())
 }
 
+fn webhook_options(args: &Args) -> Result<Option<webhooks::Options>, anyhow::Error> {
+    let Some(webhook_url) = &args.webhook_url else {
+        return Ok(None);
+    };
+    let url = reqwest::Url::parse(webhook_url)
+        .map_err(|err| anyhow!("Invalid webhook URL {}: {}", webhook_url, err))?;
+    let options = webhooks::Options::new(args.webhook_listen, url);
+    match &args.webhook_secret_token {
+        Some(secret_token) => {
+            // Options::secret_token panics on an invalid token.
+            let is_valid = (1..=256).contains(&secret_token.len())
+                && secret_token
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+            if !is_valid {
+                return Err(anyhow!(
+                    "Invalid webhook secret token. It should have 1-256 characters: A-Z, a-z, 0-9, _ and -."
+                ));
+            }
+            Ok(Some(options.secret_token(secret_token.to_owned())))
+        }
+        None => Ok(Some(options)),
+    }
+}
+
 async fn handle_media_message(
     bot: &Bot,
     app_context: Arc<ApplicationContext>,
-    photoservice: Arc<PhotoPrismPhotoService>,
     m: Message,
 ) -> Result<(), anyhow::Error> {
     if m.document().is_none() && app_context.disallow_compressed_files {
         bot.send_message(m.chat.id, t!("error-attach-media-as-document"))
             .reply_to_message_id(m.id)
             .await?;
-        Ok(())
-    } else {
-        let file_id_opt = get_file_id(&m);
-        let upload_started_message = bot
-            .send_message(m.chat.id, t!("upload-started"))
-            .reply_to_message_id(m.id)
-            .await?;
-        match file_id_opt {
-            Some(file_id) => {
-                debug!("file_id: {}", file_id);
-                let downloaded_file_path =
-                    download_file(bot, file_id, &app_context.working_dir).await?;
-                let photo_uid_result = photoservice.upload_photo(&downloaded_file_path).await;
-                tokio::fs::remove_file(downloaded_file_path).await?;
-                let photo_uid = photo_uid_result?;
-
-                if app_context.tags.is_empty() {
-                    bot.edit_message_text(
-                        upload_started_message.chat.id,
-                        upload_started_message.id,
-                        t!("success-file-is-uploaded-without-tags"),
-                    )
-                    .await?;
+        return Ok(());
+    }
+
+    match m.media_group_id().map(str::to_owned) {
+        Some(media_group_id) => {
+            // Telegram sends every file of an album as a separate message.
+            // The handler of the first message waits for the rest and uploads them all together.
+            if !app_context.add_to_media_group(&media_group_id, m) {
+                return Ok(());
+            }
+            tokio::time::sleep(app_context.media_group_timeout).await;
+            let messages = app_context.take_media_group(&media_group_id);
+            enqueue_upload(bot, &app_context, messages).await
+        }
+        None => enqueue_upload(bot, &app_context, vec![m]).await,
+    }
+}
+
+/// Queues the files of the messages, so they are uploaded even if PhotoPrism is not available right now.
+async fn enqueue_upload(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    messages: Vec<Message>,
+) -> Result<(), anyhow::Error> {
+    let first_message = &messages[0];
+    let upload_started_message = bot
+        .send_message(first_message.chat.id, t!("upload-started"))
+        .reply_to_message_id(first_message.id)
+        .await?;
+    app_context
+        .upload_queue
+        .push(UploadTask {
+            chat_id: upload_started_message.chat.id.0,
+            status_message_id: upload_started_message.id.0,
+            messages,
+        })
+        .await?;
+    app_context.upload_queue_changed.notify_one();
+    Ok(())
+}
+
+/// Files of an upload which are stored in the library.
+struct UploadedFiles {
+    photo_uids: Vec<PhotoUID>,
+    already_uploaded_count: usize,
+}
+
+/// Uploads the files of the messages with their captions, dates and labels.
+/// The result is reported by [`report_upload`].
+async fn upload_media<S: PhotoService>(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<S>,
+    messages: &[Message],
+    status_message_id: MessageId,
+) -> Result<UploadedFiles, anyhow::Error> {
+    let photoservice = chat.photoservice.as_ref();
+
+    // Telegram shows the caption of one album message as the caption of the whole album.
+    let album_caption = messages.iter().find_map(Caption::parse);
+    let mut photo_uids: Vec<PhotoUID> = Vec::with_capacity(messages.len());
+    let mut already_uploaded_count = 0;
+    for m in messages {
+        match get_file(m) {
+            Some(file) => {
+                debug!("file_id: {}", file.id);
+                let media_type = media_type(m);
+                let upload_result = if app_context.stream_uploads
+                    && photoservice.can_upload_stream()
+                {
+                    stream_file(bot, photoservice, file.id.to_owned()).await
                 } else {
-                    bot.edit_message_text(
-                        upload_started_message.chat.id,
-                        upload_started_message.id,
-                        t!("success-file-is-uploaded"),
-                    )
-                    .reply_markup(make_tags_keyboard(
-                        &TagKeyboardData {
-                            id: -1,
-                            values: vec![],
-                            photo_uid: photo_uid.0,
-                        },
-                        &app_context.tags,
-                    ))
+                    let download_timer = metrics().telegram_download_duration.start_timer();
+                    let downloaded_file =
+                        download_file(bot, file.id.to_owned(), &app_context.working_dir).await?;
+                    download_timer.observe_duration();
+                    let upload_result = async {
+                        let sha1 = downloaded_file.sha1.clone();
+                        // The same photo is often forwarded to the chat more than once.
+                        match photoservice.find_photo_by_hash(&sha1).await? {
+                            Some(photo_uid) => Ok::<_, anyhow::Error>((sha1, photo_uid, true)),
+                            None => {
+                                let photo_uid = photoservice
+                                    .upload_photo_with_hash(&downloaded_file.path, &sha1)
+                                    .await?;
+                                Ok((sha1, photo_uid, false))
+                            }
+                        }
+                    }
+                    .await;
+                    tokio::fs::remove_file(downloaded_file.path).await?;
+                    upload_result
+                };
+                let (sha1, photo_uid, already_uploaded) = match upload_result {
+                    Ok(result) => result,
+                    Err(err) => {
+                        metrics()
+                            .uploads
+                            .with_label_values(&[media_type, "failed"])
+                            .inc();
+                        return Err(err);
+                    }
+                };
+                if already_uploaded {
+                    debug!("file {} is already uploaded as {}", file.id, photo_uid.0);
+                    already_uploaded_count += 1;
+                    metrics()
+                        .uploads
+                        .with_label_values(&[media_type, "duplicate"])
+                        .inc();
+                } else {
+                    metrics()
+                        .uploads
+                        .with_label_values(&[media_type, "uploaded"])
+                        .inc();
+                    metrics()
+                        .uploaded_bytes
+                        .with_label_values(&[media_type])
+                        .inc_by(file.size.into());
+                }
+                let caption = Caption::parse(m);
+                let caption = caption.as_ref().or(album_caption.as_ref());
+                if let Some(caption) = caption {
+                    apply_caption(app_context, photoservice, &photo_uid, caption).await?;
+                }
+                // A forwarded photo has been taken before the original message was sent.
+                apply_message_date(photoservice, &photo_uid, m.forward_date().unwrap_or(m.date))
                     .await?;
+                for label in &chat.labels {
+                    photoservice.add_label(&photo_uid, label).await?;
                 }
-                Ok(())
+                app_context
+                    .message_store
+                    .insert(UploadRecord {
+                        chat_id: m.chat.id.0,
+                        message_id: m.id.0,
+                        status_message_id: Some(status_message_id.0),
+                        file_unique_id: file.unique_id.to_owned(),
+                        sha1,
+                        photo_uid: photo_uid.to_owned(),
+                        uploaded_at: Utc::now(),
+                        labels: chat
+                            .labels
+                            .iter()
+                            .chain(caption.iter().flat_map(|c| c.hashtags.iter()))
+                            .cloned()
+                            .collect(),
+                    })
+                    .await?;
+                photo_uids.push(photo_uid);
             }
-            None => Err(anyhow!("File from message has not been found.")),
+            None => return Err(anyhow!("File from message has not been found.")),
         }
     }
+    Ok(UploadedFiles {
+        photo_uids,
+        already_uploaded_count,
+    })
+}
+
+/// Reports the uploaded files in the status message and offers the tag and album keyboards.
+/// The files are already stored, so failures are logged instead of failing the upload.
+async fn report_upload<S: PhotoService>(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<S>,
+    status_message_id: MessageId,
+    uploaded_files: &UploadedFiles,
+) {
+    if let Err(err) =
+        send_upload_status(bot, app_context, chat, status_message_id, uploaded_files).await
+    {
+        warn!("Failed to report upload {}: {:?}", status_message_id, err);
+    }
+
+    if let Some(album_title) = &chat.album {
+        let result = async {
+            let album = find_or_create_album(chat.photoservice.as_ref(), album_title).await?;
+            add_to_album(
+                app_context,
+                chat.photoservice.as_ref(),
+                chat.id,
+                status_message_id,
+                &album.uid,
+            )
+            .await
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(
+                "Failed to add upload {} to album {}: {:?}",
+                status_message_id, album_title, err
+            );
+        }
+    }
+
+    if app_context.choose_album {
+        let result = async {
+            let state = AlbumKeyboardState::new(chat.photoservice.list_albums().await?);
+            let keyboard_message = bot
+                .send_message(chat.id, t!("choose-album"))
+                .reply_to_message_id(status_message_id)
+                .reply_markup(make_album_keyboard(&state))
+                .await?;
+            app_context
+                .album_keyboards
+                .insert((keyboard_message.chat.id, keyboard_message.id), state)
+                .await;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(
+                "Failed to offer albums for upload {}: {:?}",
+                status_message_id, err
+            );
+        }
+    }
+}
+
+/// Edits the status message to tell how many files have been uploaded, with the tag keyboard if there are tags.
+async fn send_upload_status<S: PhotoService>(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<S>,
+    status_message_id: MessageId,
+    uploaded_files: &UploadedFiles,
+) -> Result<(), anyhow::Error> {
+    let photoservice = chat.photoservice.as_ref();
+    let UploadedFiles {
+        photo_uids,
+        already_uploaded_count,
+    } = uploaded_files;
+    let already_uploaded_count = *already_uploaded_count;
+    // The upload is reported without the tag keyboard if the tags can not be fetched.
+    let tags = chat.tags().await.unwrap_or_else(|err| {
+        warn!("Failed to get the tags of chat {}: {:?}", chat.id, err);
+        Arc::default()
+    });
+    let mut success_message = match (photo_uids.len(), tags.is_empty()) {
+        (1, true) if already_uploaded_count == 1 => t!("success-file-already-exists-without-tags"),
+        (1, false) if already_uploaded_count == 1 => t!("success-file-already-exists"),
+        (1, true) => t!("success-file-is-uploaded-without-tags"),
+        (1, false) => t!("success-file-is-uploaded"),
+        (count, true) => t!("success-files-are-uploaded-without-tags", count = count),
+        (count, false) => t!("success-files-are-uploaded", count = count),
+    }
+    .into_owned();
+    if photo_uids.len() > 1 && already_uploaded_count > 0 {
+        success_message.push('\n');
+        success_message.push_str(&t!("files-already-exist", count = already_uploaded_count));
+    }
+    // With several backends every one of them tells whether it has stored the files.
+    let mut target_failures: Vec<(String, usize)> = vec![];
+    for photo_uid in photo_uids {
+        for (target, is_stored) in photoservice.stored_at(photo_uid) {
+            match target_failures.iter_mut().find(|(name, _)| *name == target) {
+                Some((_, failed_count)) => *failed_count += usize::from(!is_stored),
+                None => target_failures.push((target, usize::from(!is_stored))),
+            }
+        }
+    }
+    for (target, failed_count) in target_failures {
+        success_message.push('\n');
+        match failed_count {
+            0 => success_message.push_str(&t!("target-stored", target = target)),
+            _ => success_message.push_str(&t!(
+                "target-failed",
+                target = target,
+                count = failed_count,
+                total = photo_uids.len()
+            )),
+        }
+    }
+
+    if tags.is_empty() {
+        bot.edit_message_text(chat.id, status_message_id, success_message)
+            .await?;
+    } else {
+        let state = TagKeyboardState::new(tags);
+        app_context
+            .tag_keyboards
+            .insert((chat.id, status_message_id), state.clone())
+            .await;
+        bot.edit_message_text(chat.id, status_message_id, success_message)
+            .reply_markup(make_tags_keyboard(&state, app_context.tags_page_size))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Message caption split into hashtags and the remaining plain text.
+struct Caption {
+    text: String,
+    hashtags: Vec<String>,
+}
+
+impl Caption {
+    fn parse(message: &Message) -> Option<Self> {
+        let caption = message.caption()?;
+        let entities = message.parse_caption_entities().unwrap_or_default();
+        let mut text = String::with_capacity(caption.len());
+        let mut hashtags = Vec::new();
+        let mut position = 0;
+        for entity in entities
+            .iter()
+            .filter(|e| *e.kind() == MessageEntityKind::Hashtag)
+        {
+            text.push_str(&caption[position..entity.start()]);
+            position = entity.end();
+            hashtags.push(entity.text().trim_start_matches('#').to_owned());
+        }
+        text.push_str(&caption[position..]);
+        Some(Self::new(&text, hashtags))
+    }
+
+    fn new(text: &str, hashtags: Vec<String>) -> Self {
+        let text = text
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self { text, hashtags }
+    }
+
+    fn title(&self) -> Option<&str> {
+        self.text.lines().next()
+    }
+}
+
+async fn apply_caption(
+    app_context: &ApplicationContext,
+    photoservice: &impl PhotoService,
+    photo_uid: &PhotoUID,
+    caption: &Caption,
+) -> Result<(), anyhow::Error> {
+    let details = PhotoDetails {
+        title: caption
+            .title()
+            .filter(|_| app_context.caption_as_title)
+            .map(str::to_owned),
+        description: Some(caption.text.to_owned()).filter(|text| !text.is_empty()),
+        taken_at: None,
+    };
+    if !details.is_empty() {
+        photoservice.update_photo(photo_uid, &details).await?;
+    }
+    for hashtag in &caption.hashtags {
+        photoservice.add_label(photo_uid, hashtag).await?;
+    }
+    Ok(())
+}
+
+/// Compressed photos have no EXIF, so PhotoPrism dates them by the upload time.
+/// Such photos are dated by the message instead.
+async fn apply_message_date(
+    photoservice: &impl PhotoService,
+    photo_uid: &PhotoUID,
+    date: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    if !photoservice.has_capture_date(photo_uid).await? {
+        let details = PhotoDetails {
+            taken_at: Some(date),
+            ..PhotoDetails::default()
+        };
+        photoservice.update_photo(photo_uid, &details).await?;
+    }
+    Ok(())
 }
 
 // TODO: Need to add progress bar.
+/// File downloaded from Telegram together with its SHA1 hash.
+struct DownloadedFile {
+    path: PathBuf,
+    sha1: String,
+}
+
+/// Downloads the file to the working directory, calculating its hash while it is written.
 async fn download_file(
     bot: &Bot,
     file_id: String,
     working_dir: &OsString,
-) -> Result<PathBuf, anyhow::Error> {
+) -> Result<DownloadedFile, anyhow::Error> {
     let file = bot.get_file(file_id).await?;
     let path = Path::new(&file.path);
     if path.is_absolute() {
         /* Telegram bot api server is used in local mode.
         Do not need to download anything because file will be downloaded
         on bot.get_file call by telegram bot api server. */
-        Ok(path.to_path_buf())
+        Ok(DownloadedFile {
+            path: path.to_path_buf(),
+            sha1: calculate_sha1(path).await?,
+        })
     } else {
         let file_extension = Path::new(&file.path)
             .extension()
@@ -243,79 +1076,390 @@ async fn download_file(
         path_buf.push(&file.id);
         path_buf.set_extension(file_extension);
         let mut f = tokio::fs::File::create(&path_buf).await?;
-        bot.download_file(&file.path, &mut f).await?;
-        Ok(path_buf)
+        let hasher = StreamHasher::default();
+        let mut stream = hasher.wrap(bot.download_file_stream(&file.path));
+        while let Some(bytes) = stream.try_next().await? {
+            f.write_all(&bytes).await?;
+        }
+        f.flush().await?;
+        Ok(DownloadedFile {
+            path: path_buf,
+            sha1: hasher.sha1(),
+        })
     }
 }
 
-async fn handle_callback_message(
+/// Uploads the file while it is downloaded from Telegram, without storing it in the working directory.
+/// Returns the SHA1 hash of the file, its UID and whether the library has already had the file.
+async fn stream_file(
+    bot: &Bot,
+    photoservice: &impl PhotoService,
+    file_id: String,
+) -> Result<(String, PhotoUID, bool), anyhow::Error> {
+    let file = bot.get_file(file_id).await?;
+    let path = Path::new(&file.path);
+    let file_name = format!(
+        "{}.{}",
+        file.id,
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or(UNKNOWN_EXTENSION)
+    );
+    let stream: FileStream = if path.is_absolute() {
+        // The local Telegram bot api server has already stored the file.
+        let local_file = tokio::fs::File::open(path).await?;
+        FramedRead::new(local_file, BytesCodec::new())
+            .map_ok(BytesMut::freeze)
+            .map_err(anyhow::Error::from)
+            .boxed()
+    } else {
+        bot.download_file_stream(&file.path)
+            .map_err(anyhow::Error::from)
+            .boxed()
+    };
+    let streamed_file = photoservice.upload_stream(&file_name, stream).await?;
+    Ok((
+        streamed_file.sha1,
+        streamed_file.photo_uid,
+        streamed_file.already_uploaded,
+    ))
+}
+
+async fn handle_callback_message<S: PhotoService>(
     bot: &Bot,
     app_context: Arc<ApplicationContext>,
-    photoservice: Arc<PhotoPrismPhotoService>,
+    chat: Arc<ChatContext<S>>,
     q: CallbackQuery,
 ) -> Result<(), anyhow::Error> {
-    if let (Some(keyboard_data_str), Some(Message { id, chat, .. })) = (q.data, q.message) {
-        let tag_keyboard_data: TagKeyboardData = serde_json::from_str(&keyboard_data_str)?;
-        let new_keyboard = make_tags_keyboard(&tag_keyboard_data, &app_context.tags);
-        if tag_keyboard_data.id == TagKeyboardData::SAVE_BUTTON_ID {
-            let mut selected_tags: Vec<String> = Vec::new();
-            for tag_id in tag_keyboard_data.values {
-                selected_tags.push(app_context.tags[tag_id as usize].to_owned());
+    let mut answer = bot.answer_callback_query(q.id);
+    if let (Some(keyboard_data_str), Some(message)) = (q.data, q.message) {
+        match serde_json::from_str(&keyboard_data_str)? {
+            KeyboardData::Tags(tag_keyboard_data) => {
+                let is_handled =
+                    handle_tags_keyboard(bot, &app_context, &chat, &message, tag_keyboard_data)
+                        .await?;
+                if !is_handled {
+                    answer = answer.text(t!("tag-keyboard-expired")).show_alert(true);
+                }
             }
-            let photo_uid = &PhotoUID(tag_keyboard_data.photo_uid.to_owned());
-            for tag in &selected_tags {
-                photoservice.add_label(photo_uid, tag).await?;
+            KeyboardData::Album(album_keyboard_data) => {
+                let is_handled = handle_album_keyboard(
+                    bot,
+                    &app_context,
+                    chat.photoservice.as_ref(),
+                    &message,
+                    album_keyboard_data,
+                )
+                .await?;
+                if !is_handled {
+                    answer = answer.text(t!("album-keyboard-expired")).show_alert(true);
+                }
             }
+        }
+    }
+
+    answer.await?;
+    Ok(())
+}
 
-            let message = t!("success-save-tags", tags = &selected_tags.join(","));
-            bot.edit_message_text(chat.id, id, message).await?;
-        } else {
-            bot.edit_message_reply_markup(chat.id, id)
-                .reply_markup(new_keyboard)
+async fn handle_tags_keyboard<S: PhotoService>(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    chat: &ChatContext<S>,
+    message: &Message,
+    tag_keyboard_data: TagKeyboardData,
+) -> Result<bool, anyhow::Error> {
+    let state_key = (message.chat.id, message.id);
+    // The state is lost if the bot has been restarted or the keyboard has not been used for a long time.
+    let Some(state) = app_context.tag_keyboards.get(&state_key).await else {
+        return Ok(false);
+    };
+    if tag_keyboard_data.id == TagKeyboardData::SAVE_BUTTON_ID {
+        let selected_tags = state.tags_of(state.selected.iter().copied());
+        let added_tags = state.tags_of(
+            state
+                .selected
+                .iter()
+                .copied()
+                .filter(|i| !state.saved.contains(i)),
+        );
+        let removed_tags = state.tags_of(
+            state
+                .saved
+                .iter()
+                .copied()
+                .filter(|i| !state.selected.contains(i)),
+        );
+        let mut records = find_upload_records(app_context, message.chat.id, message.id).await;
+        // A tag keyboard opened by /tags is a reply to the upload.
+        if records.is_empty() {
+            if let Some(upload_message) = message.reply_to_message() {
+                records =
+                    find_upload_records(app_context, message.chat.id, upload_message.id).await;
+            }
+        }
+        if records.is_empty() {
+            return Err(anyhow!("Photos for the tag keyboard have not been found."));
+        }
+        for record in &records {
+            for tag in &added_tags {
+                chat.photoservice.add_label(&record.photo_uid, tag).await?;
+            }
+            for tag in &removed_tags {
+                chat.photoservice
+                    .remove_label(&record.photo_uid, tag)
+                    .await?;
+            }
+            app_context
+                .message_store
+                .add_labels(&record.photo_uid, &added_tags)
+                .await?;
+            app_context
+                .message_store
+                .remove_labels(&record.photo_uid, &removed_tags)
                 .await?;
         }
+
+        app_context.tag_keyboards.invalidate(&state_key).await;
+
+        let success_message = t!("success-save-tags", tags = &selected_tags.join(","));
+        bot.edit_message_text(message.chat.id, message.id, success_message)
+            .await?;
+    } else {
+        let page_size = app_context.tags_page_size;
+        let button_id = tag_keyboard_data.id;
+        let state = app_context
+            .tag_keyboards
+            .entry(state_key)
+            .and_upsert_with(|entry| async move {
+                let mut state = entry.map(|e| e.into_value()).unwrap_or(state);
+                let page_count = state.page_count(page_size);
+                match button_id {
+                    TagKeyboardData::PREVIOUS_PAGE_BUTTON_ID => {
+                        state.page = state.page.saturating_sub(1);
+                    }
+                    TagKeyboardData::NEXT_PAGE_BUTTON_ID => {
+                        state.page = (state.page + 1).min(page_count.saturating_sub(1));
+                    }
+                    tag_index if tag_index >= 0 => state.toggle(tag_index as usize),
+                    _ => {}
+                }
+                state
+            })
+            .await
+            .into_value();
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .reply_markup(make_tags_keyboard(&state, app_context.tags_page_size))
+            .await?;
     }
+    Ok(true)
+}
+
+async fn handle_album_keyboard(
+    bot: &Bot,
+    app_context: &ApplicationContext,
+    photoservice: &impl PhotoService,
+    message: &Message,
+    album_keyboard_data: AlbumKeyboardData,
+) -> Result<bool, anyhow::Error> {
+    // The album keyboard is sent as a reply to the status message of the upload.
+    let status_message = message
+        .reply_to_message()
+        .ok_or_else(|| anyhow!("Status message for the album keyboard has not been found."))?;
 
-    bot.answer_callback_query(q.id).await?;
+    if album_keyboard_data.album == AlbumKeyboardData::NEW_ALBUM_BUTTON_ID {
+        // The prompt is not selective: it would be shown only to the bot, whose message it replies to.
+        let prompt = bot
+            .send_message(message.chat.id, t!("enter-album-name"))
+            .reply_to_message_id(message.id)
+            .reply_markup(ForceReply::new())
+            .await?;
+        app_context
+            .album_name_prompts
+            .insert((prompt.chat.id, prompt.id), status_message.id)
+            .await;
+        return Ok(true);
+    }
+
+    let state_key = (message.chat.id, message.id);
+    // The albums are lost if the bot has been restarted or the keyboard has not been used for a long time.
+    let Some(mut state) = app_context.album_keyboards.get(&state_key).await else {
+        return Ok(false);
+    };
+    match album_keyboard_data.album {
+        AlbumKeyboardData::PREVIOUS_PAGE_BUTTON_ID => state.page = state.page.saturating_sub(1),
+        AlbumKeyboardData::NEXT_PAGE_BUTTON_ID => {
+            state.page = (state.page + 1).min(state.page_count().saturating_sub(1));
+        }
+        album_index if album_index >= 0 => {
+            let album = state.albums.get(album_index as usize).ok_or_else(|| {
+                anyhow!("Album {} of the keyboard has not been found.", album_index)
+            })?;
+            add_to_album(
+                app_context,
+                photoservice,
+                status_message.chat.id,
+                status_message.id,
+                &album.uid,
+            )
+            .await?;
+            app_context.album_keyboards.invalidate(&state_key).await;
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                t!("success-add-to-album", album = album.title),
+            )
+            .await?;
+            return Ok(true);
+        }
+        _ => return Ok(true),
+    }
+    app_context
+        .album_keyboards
+        .insert(state_key, state.clone())
+        .await;
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .reply_markup(make_album_keyboard(&state))
+        .await?;
+    Ok(true)
+}
+
+async fn find_album_name_prompt(
+    m: Message,
+    app_context: Arc<ApplicationContext>,
+) -> Option<AlbumNamePrompt> {
+    let prompt = m.reply_to_message()?;
+    m.text()?;
+    let status_message_id = app_context
+        .album_name_prompts
+        .get(&(prompt.chat.id, prompt.id))
+        .await?;
+    Some(AlbumNamePrompt { status_message_id })
+}
+
+async fn handle_album_name_message<S: PhotoService>(
+    bot: &Bot,
+    app_context: Arc<ApplicationContext>,
+    chat: Arc<ChatContext<S>>,
+    m: Message,
+    prompt: AlbumNamePrompt,
+) -> Result<(), anyhow::Error> {
+    let album_title = m.text().unwrap_or_default().trim();
+    let album = chat.photoservice.create_album(album_title).await?;
+    add_to_album(
+        &app_context,
+        chat.photoservice.as_ref(),
+        m.chat.id,
+        prompt.status_message_id,
+        &album.uid,
+    )
+    .await?;
+    if let Some(prompt_message) = m.reply_to_message() {
+        app_context
+            .album_name_prompts
+            .invalidate(&(prompt_message.chat.id, prompt_message.id))
+            .await;
+    }
+
+    bot.send_message(m.chat.id, t!("success-add-to-album", album = album.title))
+        .reply_to_message_id(m.id)
+        .await?;
     Ok(())
 }
 
-fn get_file_id(message: &Message) -> Option<String> {
-    let maybe_photo_file_id: Option<String> = message
+async fn find_or_create_album(
+    photoservice: &impl PhotoService,
+    album_title: &str,
+) -> Result<Album, anyhow::Error> {
+    let albums = photoservice.list_albums().await?;
+    match albums.into_iter().find(|album| album.title == album_title) {
+        Some(album) => Ok(album),
+        None => Ok(photoservice.create_album(album_title).await?),
+    }
+}
+
+async fn add_to_album(
+    app_context: &ApplicationContext,
+    photoservice: &impl PhotoService,
+    chat_id: ChatId,
+    status_message_id: MessageId,
+    album_uid: &str,
+) -> Result<(), anyhow::Error> {
+    let records = app_context
+        .message_store
+        .find_by_status_message(chat_id.0, status_message_id.0)
+        .await;
+    if records.is_empty() {
+        return Err(anyhow!("Photos for the album have not been found."));
+    }
+    let photo_uids: Vec<PhotoUID> = records.into_iter().map(|r| r.photo_uid).collect();
+    photoservice.add_to_album(album_uid, &photo_uids).await?;
+    Ok(())
+}
+
+/// Returns the uploads reported by the status message, or the upload of the media message.
+async fn find_upload_records(
+    app_context: &ApplicationContext,
+    chat_id: ChatId,
+    message_id: MessageId,
+) -> Vec<UploadRecord> {
+    let records = app_context
+        .message_store
+        .find_by_status_message(chat_id.0, message_id.0)
+        .await;
+    if !records.is_empty() {
+        return records;
+    }
+    app_context
+        .message_store
+        .find_by_message(chat_id.0, message_id.0)
+        .await
+        .into_iter()
+        .collect()
+}
+
+fn get_file(message: &Message) -> Option<&FileMeta> {
+    let maybe_photo_file: Option<&FileMeta> = message
         .photo()
         .and_then(|photo_sizes| photo_sizes.last())
-        .map(|photo_size| photo_size.file.id.clone());
-    let maybe_video_file_id: Option<String> = message.video().map(|v| v.file.id.to_owned());
+        .map(|photo_size| &photo_size.file);
+    let maybe_video_file: Option<&FileMeta> = message.video().map(|v| &v.file);
     // TODO: Add filter by extension
-    let maybe_document_file_id: Option<String> = message.document().map(|d| d.file.id.clone());
-    maybe_document_file_id
-        .or(maybe_photo_file_id)
-        .or(maybe_video_file_id)
+    let maybe_document_file: Option<&FileMeta> = message.document().map(|d| &d.file);
+    maybe_document_file
+        .or(maybe_photo_file)
+        .or(maybe_video_file)
 }
 
-fn make_tags_keyboard(data: &TagKeyboardData, tags: &[String]) -> InlineKeyboardMarkup {
-    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-    let mut new_values = data.values.to_owned();
-    let element_pos = data.values.iter().position(|&x| x == data.id);
-    if let Some(pos) = element_pos {
-        new_values.remove(pos);
-    } else if data.id >= 0 {
-        new_values.push(data.id)
+// Name of the media type used as a metric label, in the order get_file picks the file.
+fn media_type(message: &Message) -> &'static str {
+    if message.document().is_some() {
+        "document"
+    } else if message.photo().is_some() {
+        "photo"
+    } else {
+        "video"
     }
+}
 
-    for (chunk_index, tags) in tags.chunks(3).enumerate() {
+fn make_tags_keyboard(state: &TagKeyboardState, page_size: usize) -> InlineKeyboardMarkup {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
+    let tags = &state.tags;
+    let page_count = state.page_count(page_size);
+    let page = state.page.min(page_count.saturating_sub(1));
+    let page_start = page * page_size;
+    let page_tags = &tags[page_start..(page_start + page_size).min(tags.len())];
+    for (chunk_index, tags) in page_tags.chunks(TagKeyboardData::TAGS_PER_ROW).enumerate() {
         let row = tags
             .iter()
             .enumerate()
             .map(|(index, tag)| {
-                let global_index = (chunk_index * 3 + index) as i32;
+                let global_index = page_start + chunk_index * TagKeyboardData::TAGS_PER_ROW + index;
                 let callback_data = TagKeyboardData {
-                    id: global_index,
-                    values: new_values.to_owned(),
-                    photo_uid: data.photo_uid.to_owned(),
+                    id: global_index as i32,
                 };
                 let callback_data_as_string = serde_json::to_string(&callback_data).unwrap();
-                if new_values.contains(&global_index) {
+                if state.selected.contains(&global_index) {
                     InlineKeyboardButton::callback(
                         format!("{}{}", tag, TagKeyboardData::CHECK_MARK_SYMBOL),
                         callback_data_as_string,
@@ -328,10 +1472,23 @@ fn make_tags_keyboard(data: &TagKeyboardData, tags: &[String]) -> InlineKeyboard
         keyboard.push(row);
     }
 
+    if page_count > 1 {
+        keyboard.push(make_page_buttons(
+            page,
+            page_count,
+            serde_json::to_string(&TagKeyboardData {
+                id: TagKeyboardData::PREVIOUS_PAGE_BUTTON_ID,
+            })
+            .unwrap(),
+            serde_json::to_string(&TagKeyboardData {
+                id: TagKeyboardData::NEXT_PAGE_BUTTON_ID,
+            })
+            .unwrap(),
+        ));
+    }
+
     let callback_data = TagKeyboardData {
         id: TagKeyboardData::SAVE_BUTTON_ID,
-        values: new_values.to_owned(),
-        photo_uid: data.photo_uid.to_owned(),
     };
     let callback_data_as_string = serde_json::to_string(&callback_data).unwrap();
 
@@ -342,18 +1499,125 @@ fn make_tags_keyboard(data: &TagKeyboardData, tags: &[String]) -> InlineKeyboard
     InlineKeyboardMarkup::new(keyboard)
 }
 
+/// Makes the buttons to the previous and the next page of a keyboard with several pages.
+fn make_page_buttons(
+    page: usize,
+    page_count: usize,
+    previous_page_data: String,
+    next_page_data: String,
+) -> Vec<InlineKeyboardButton> {
+    let mut navigation_row = vec![];
+    if page > 0 {
+        navigation_row.push(InlineKeyboardButton::callback(
+            format!("\u{00AB} {}/{}", page, page_count),
+            previous_page_data,
+        ));
+    }
+    if page + 1 < page_count {
+        navigation_row.push(InlineKeyboardButton::callback(
+            format!("{}/{} \u{00BB}", page + 2, page_count),
+            next_page_data,
+        ));
+    }
+    navigation_row
+}
+
+fn make_album_keyboard(state: &AlbumKeyboardState) -> InlineKeyboardMarkup {
+    let album_callback_data =
+        |album| serde_json::to_string(&KeyboardData::Album(AlbumKeyboardData { album })).unwrap();
+    let albums = &state.albums;
+    let page_count = state.page_count();
+    let page = state.page.min(page_count.saturating_sub(1));
+    let page_start = page * AlbumKeyboardData::ALBUMS_PER_PAGE;
+    let page_albums =
+        &albums[page_start..(page_start + AlbumKeyboardData::ALBUMS_PER_PAGE).min(albums.len())];
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = page_albums
+        .chunks(AlbumKeyboardData::ALBUMS_PER_ROW)
+        .enumerate()
+        .map(|(chunk_index, albums)| {
+            albums
+                .iter()
+                .enumerate()
+                .map(|(index, album)| {
+                    let global_index =
+                        page_start + chunk_index * AlbumKeyboardData::ALBUMS_PER_ROW + index;
+                    InlineKeyboardButton::callback(
+                        &album.title,
+                        album_callback_data(global_index as i32),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    if page_count > 1 {
+        keyboard.push(make_page_buttons(
+            page,
+            page_count,
+            album_callback_data(AlbumKeyboardData::PREVIOUS_PAGE_BUTTON_ID),
+            album_callback_data(AlbumKeyboardData::NEXT_PAGE_BUTTON_ID),
+        ));
+    }
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        t!("new-album"),
+        album_callback_data(AlbumKeyboardData::NEW_ALBUM_BUTTON_ID),
+    )]);
+    InlineKeyboardMarkup::new(keyboard)
+}
+
 // TODO: Can error handling with dependencies be done more elegantly?
+async fn handle_command_with_error<S: PhotoService>(
+    bot: Bot,
+    app_context: Arc<ApplicationContext>,
+    chat: Arc<ChatContext<S>>,
+    m: Message,
+    command: ChatCommand,
+) -> Result<(), anyhow::Error> {
+    match handle_command(&bot, &app_context, &chat, &m, command).await {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            metrics().observe_error(&err);
+            bot.send_message(m.chat.id, t!("error-panic"))
+                .reply_to_message_id(m.id)
+                .await?;
+            Err(err)
+        }
+    }
+}
+
 async fn handle_media_message_with_error(
     bot: Bot,
     app_context: Arc<ApplicationContext>,
-    photoservice: Arc<PhotoPrismPhotoService>,
     m: Message,
 ) -> Result<(), anyhow::Error> {
     let chat_id = m.chat.id;
     let message_id = m.id;
-    match handle_media_message(&bot, app_context, photoservice, m).await {
+    match handle_media_message(&bot, app_context, m).await {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            metrics().observe_error(&err);
+            bot.send_message(chat_id, t!("error-panic"))
+                .reply_to_message_id(message_id)
+                .await?;
+            Err(err)
+        }
+    }
+}
+
+async fn handle_album_name_message_with_error<S: PhotoService>(
+    bot: Bot,
+    app_context: Arc<ApplicationContext>,
+    chat: Arc<ChatContext<S>>,
+    m: Message,
+    prompt: AlbumNamePrompt,
+) -> Result<(), anyhow::Error> {
+    let chat_id = m.chat.id;
+    let message_id = m.id;
+    match handle_album_name_message(&bot, app_context, chat, m, prompt).await {
         Ok(()) => Ok(()),
         Err(err) => {
+            metrics().observe_error(&err);
             bot.send_message(chat_id, t!("error-panic"))
                 .reply_to_message_id(message_id)
                 .await?;
@@ -362,18 +1626,19 @@ async fn handle_media_message_with_error(
     }
 }
 
-async fn handle_callback_message_with_error(
+async fn handle_callback_message_with_error<S: PhotoService>(
     bot: Bot,
     app_context: Arc<ApplicationContext>,
-    photoservice: Arc<PhotoPrismPhotoService>,
+    chat: Arc<ChatContext<S>>,
     q: CallbackQuery,
 ) -> Result<(), anyhow::Error> {
     let chat_id_opt = q.message.as_ref().map(|x| x.chat.id);
     let message_id_opt = q.message.as_ref().map(|x| x.id);
     if let (Some(chat_id), Some(message_id)) = (chat_id_opt, message_id_opt) {
-        match handle_callback_message(&bot, app_context, photoservice, q).await {
+        match handle_callback_message(&bot, app_context, chat, q).await {
             Ok(()) => Ok(()),
             Err(err) => {
+                metrics().observe_error(&err);
                 bot.edit_message_text(chat_id, message_id, t!("error-panic"))
                     .await?;
                 Err(err)
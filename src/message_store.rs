@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::PhotoUID;
+
+/// Link between a Telegram message and the photo uploaded from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadRecord {
+    pub chat_id: i64,
+    pub message_id: i32,
+    /// Message of the bot which reports the upload status and holds the tag keyboard.
+    /// Files imported from a chat export have none.
+    pub status_message_id: Option<i32>,
+    /// Telegram file_unique_id, or the path of the file inside a chat export.
+    pub file_unique_id: String,
+    pub sha1: String,
+    pub photo_uid: PhotoUID,
+    pub uploaded_at: DateTime<Utc>,
+    pub labels: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum MessageStoreError {
+    #[error("Failed to access message store: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Message store {file} is corrupted at line {line}: {err}")]
+    Corrupted {
+        file: String,
+        line: usize,
+        err: serde_json::Error,
+    },
+}
+
+/// Line of the journal telling that the record of a message has been removed.
+#[derive(Serialize, Deserialize)]
+struct RemovedRecord {
+    chat_id: i64,
+    message_id: i32,
+    removed: bool,
+}
+
+/// Line of the journal: the latest version of a record, or the removal of the record of a message.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JournalEntry {
+    Record(UploadRecord),
+    Removed(RemovedRecord),
+}
+
+/// Persistent mapping between Telegram messages and uploaded photos.
+///
+/// Records are kept in memory and appended to a JSON lines journal on every change.
+/// The latest line of a message wins, so the journal is compacted every time the store is opened.
+pub struct MessageStore {
+    state: Mutex<MessageStoreState>,
+}
+
+struct MessageStoreState {
+    records: HashMap<(i64, i32), UploadRecord>,
+    journal: File,
+}
+
+impl MessageStore {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, MessageStoreError> {
+        let path = path.as_ref();
+        let records = Self::read_journal(path).await?;
+
+        // Rewrite the journal with the latest version of every record only.
+        let compacted_path = path.with_extension("compacted");
+        let mut compacted = File::create(&compacted_path).await?;
+        for record in records.values() {
+            compacted.write_all(&Self::journal_line(record)).await?;
+        }
+        compacted.sync_all().await?;
+        tokio::fs::rename(&compacted_path, path).await?;
+
+        let journal = OpenOptions::new().append(true).open(path).await?;
+        Ok(Self {
+            state: Mutex::new(MessageStoreState { records, journal }),
+        })
+    }
+
+    async fn read_journal(
+        path: &Path,
+    ) -> Result<HashMap<(i64, i32), UploadRecord>, MessageStoreError> {
+        let mut records = HashMap::new();
+        let file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(records),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut lines = BufReader::new(file).lines();
+        let mut line_number = 0;
+        let mut next_line = lines.next_line().await?;
+        while let Some(line) = next_line {
+            line_number += 1;
+            next_line = lines.next_line().await?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: JournalEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                // A crash while the record was written leaves it incomplete at the end of the journal.
+                Err(err) if next_line.is_none() => {
+                    warn!(
+                        "Skipping incomplete line {} of {}: {}",
+                        line_number,
+                        path.display(),
+                        err
+                    );
+                    break;
+                }
+                Err(err) => {
+                    return Err(MessageStoreError::Corrupted {
+                        file: path.display().to_string(),
+                        line: line_number,
+                        err,
+                    })
+                }
+            };
+            match entry {
+                JournalEntry::Record(record) => {
+                    records.insert((record.chat_id, record.message_id), record);
+                }
+                JournalEntry::Removed(removed) => {
+                    records.remove(&(removed.chat_id, removed.message_id));
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn journal_line(record: &UploadRecord) -> Vec<u8> {
+        let mut line = serde_json::to_vec(record).unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    pub async fn insert(&self, record: UploadRecord) -> Result<(), MessageStoreError> {
+        let mut state = self.state.lock().await;
+        state
+            .journal
+            .write_all(&Self::journal_line(&record))
+            .await?;
+        state.journal.flush().await?;
+        state
+            .records
+            .insert((record.chat_id, record.message_id), record);
+        Ok(())
+    }
+
+    /// Remembers that the labels have been added to every message uploaded as the photo.
+    pub async fn add_labels(
+        &self,
+        photo_uid: &PhotoUID,
+        labels: &[String],
+    ) -> Result<(), MessageStoreError> {
+        let mut state = self.state.lock().await;
+        let MessageStoreState { records, journal } = &mut *state;
+        for record in records.values_mut().filter(|r| &r.photo_uid == photo_uid) {
+            for label in labels {
+                if !record.labels.contains(label) {
+                    record.labels.push(label.to_owned());
+                }
+            }
+            journal.write_all(&Self::journal_line(record)).await?;
+        }
+        journal.flush().await?;
+        Ok(())
+    }
+
+    /// Forgets that the labels have been added to the messages uploaded as the photo.
+    pub async fn remove_labels(
+        &self,
+        photo_uid: &PhotoUID,
+        labels: &[String],
+    ) -> Result<(), MessageStoreError> {
+        let mut state = self.state.lock().await;
+        let MessageStoreState { records, journal } = &mut *state;
+        for record in records.values_mut().filter(|r| &r.photo_uid == photo_uid) {
+            record.labels.retain(|label| !labels.contains(label));
+            journal.write_all(&Self::journal_line(record)).await?;
+        }
+        journal.flush().await?;
+        Ok(())
+    }
+
+    /// Forgets the messages uploaded as the photos, e.g. because the photos have been archived.
+    pub async fn remove_photos(&self, photo_uids: &[PhotoUID]) -> Result<(), MessageStoreError> {
+        let mut state = self.state.lock().await;
+        let MessageStoreState { records, journal } = &mut *state;
+        let removed_keys: Vec<(i64, i32)> = records
+            .iter()
+            .filter(|(_, record)| photo_uids.contains(&record.photo_uid))
+            .map(|(key, _)| *key)
+            .collect();
+        for (chat_id, message_id) in removed_keys {
+            let mut line = serde_json::to_vec(&RemovedRecord {
+                chat_id,
+                message_id,
+                removed: true,
+            })
+            .unwrap();
+            line.push(b'\n');
+            journal.write_all(&line).await?;
+            records.remove(&(chat_id, message_id));
+        }
+        journal.flush().await?;
+        Ok(())
+    }
+
+    pub async fn find_by_message(&self, chat_id: i64, message_id: i32) -> Option<UploadRecord> {
+        let state = self.state.lock().await;
+        state.records.get(&(chat_id, message_id)).cloned()
+    }
+
+    pub async fn find_by_status_message(
+        &self,
+        chat_id: i64,
+        status_message_id: i32,
+    ) -> Vec<UploadRecord> {
+        let state = self.state.lock().await;
+        let mut records: Vec<UploadRecord> = state
+            .records
+            .values()
+            .filter(|r| r.chat_id == chat_id && r.status_message_id == Some(status_message_id))
+            .cloned()
+            .collect();
+        records.sort_by_key(|r| r.message_id);
+        records
+    }
+
+    pub async fn find_by_photo_uid(&self, photo_uid: &PhotoUID) -> Vec<UploadRecord> {
+        let state = self.state.lock().await;
+        state
+            .records
+            .values()
+            .filter(|r| &r.photo_uid == photo_uid)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn find_by_sha1(&self, sha1: &str) -> Option<UploadRecord> {
+        let state = self.state.lock().await;
+        state.records.values().find(|r| r.sha1 == sha1).cloned()
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::OnceLock;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::PhotoPrismServiceError;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Returns the metrics of the bot, exposed in the Prometheus text format.
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+// Buckets in seconds. Large videos take minutes to be downloaded and indexed.
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    /// Processed files by media type and result: uploaded, duplicate or failed.
+    pub uploads: IntCounterVec,
+    /// Size of the uploaded files by media type.
+    pub uploaded_bytes: IntCounterVec,
+    pub telegram_download_duration: Histogram,
+    /// Duration of the PhotoPrism requests by stage: upload or index.
+    pub photoprism_upload_duration: HistogramVec,
+    /// Errors of the PhotoPrism service by variant of [`PhotoPrismServiceError`].
+    pub photoprism_errors: IntCounterVec,
+    /// Logins to PhotoPrism: the first one and every session refresh.
+    pub photoprism_auth_refreshes: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("telegram2photoprism".to_owned()), None).unwrap();
+        let metrics = Self {
+            uploads: IntCounterVec::new(
+                Opts::new("uploads_total", "Number of processed files"),
+                &["media_type", "result"],
+            )
+            .unwrap(),
+            uploaded_bytes: IntCounterVec::new(
+                Opts::new("uploaded_bytes_total", "Size of the uploaded files"),
+                &["media_type"],
+            )
+            .unwrap(),
+            telegram_download_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "telegram_download_duration_seconds",
+                    "Duration of the file downloads from Telegram",
+                )
+                .buckets(DURATION_BUCKETS.to_vec()),
+            )
+            .unwrap(),
+            photoprism_upload_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "photoprism_upload_duration_seconds",
+                    "Duration of the file uploads to PhotoPrism",
+                )
+                .buckets(DURATION_BUCKETS.to_vec()),
+                &["stage"],
+            )
+            .unwrap(),
+            photoprism_errors: IntCounterVec::new(
+                Opts::new("photoprism_errors_total", "Number of PhotoPrism errors"),
+                &["error"],
+            )
+            .unwrap(),
+            photoprism_auth_refreshes: IntCounter::new(
+                "photoprism_auth_refreshes_total",
+                "Number of logins to PhotoPrism",
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register().unwrap();
+        metrics
+    }
+
+    fn register(&self) -> Result<(), prometheus::Error> {
+        self.registry.register(Box::new(self.uploads.clone()))?;
+        self.registry
+            .register(Box::new(self.uploaded_bytes.clone()))?;
+        self.registry
+            .register(Box::new(self.telegram_download_duration.clone()))?;
+        self.registry
+            .register(Box::new(self.photoprism_upload_duration.clone()))?;
+        self.registry
+            .register(Box::new(self.photoprism_errors.clone()))?;
+        self.registry
+            .register(Box::new(self.photoprism_auth_refreshes.clone()))?;
+        Ok(())
+    }
+
+    /// Counts the PhotoPrism service error the given error has been caused by, if any.
+    pub fn observe_error(&self, err: &anyhow::Error) {
+        if let Some(err) = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<PhotoPrismServiceError>())
+        {
+            self.photoprism_errors
+                .with_label_values(&[err.variant_name()])
+                .inc();
+        }
+    }
+
+    /// Returns all the metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
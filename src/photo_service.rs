@@ -1,33 +1,213 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::Path;
 use std::string::ToString;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use futures_util::TryStreamExt;
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use log::warn;
 use moka::future::Cache;
 use rand::distributions::{Alphanumeric, DistString};
-use reqwest::{multipart, Body, RequestBuilder, Response, StatusCode};
-use serde::Serialize;
+use reqwest::header::HeaderValue;
+use reqwest::{multipart, Body, Method, RequestBuilder, Response, StatusCode};
+use retry::delay::{jitter, Exponential};
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use thiserror::Error;
 use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 
+use crate::metrics::metrics;
 use crate::photo_service::PhotoPrismServiceError::{
-    AccessTokenIsMissing, AddLabelFailed, AuthenticationError, CanNotFindPhotoByHash,
-    IndexingFailed, UploadFailed, UserIDIsMissing,
+    AccessTokenIsMissing, AddLabelFailed, AddToAlbumFailed, ArchiveFailed, AuthenticationError,
+    CanNotFindPhotoByHash, CreateAlbumFailed, GetPhotoFailed, IndexingFailed, ListAlbumsFailed,
+    ListLabelsFailed, ListPeopleFailed, PhotoPrismAPIError, RemoveLabelFailed, ServerNotAvailable,
+    SessionRejected, UpdatePhotoFailed, UploadFailed, UserIDIsMissing,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PhotoUID(pub String);
 
-pub trait PhotoService {
-    type Error;
+#[derive(Debug, Clone)]
+pub struct Album {
+    pub uid: String,
+    pub title: String,
+}
+
+/// File uploaded by [`PhotoService::upload_stream`].
+#[derive(Debug, Clone)]
+pub struct StreamedFile {
+    pub photo_uid: PhotoUID,
+    /// SHA1 hash of the file, calculated while the file has been sent.
+    pub sha1: String,
+    /// True if the library has already had the file.
+    pub already_uploaded: bool,
+}
+
+/// Photo metadata set by the bot. Fields which are `None` are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct PhotoDetails {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub taken_at: Option<DateTime<Utc>>,
+}
+
+impl PhotoDetails {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.taken_at.is_none()
+    }
+}
+
+// Sources of PhotoPrism's TakenAt which come from the file itself or from the user.
+// Other photos are dated by the time they have been indexed at.
+const CAPTURE_DATE_SOURCES: [&str; 4] = ["meta", "xmp", "name", "manual"];
+
+/// Calculates SHA1 of the file the same way PhotoPrism does for the file hash.
+pub async fn calculate_sha1<P: AsRef<Path>>(file_path: P) -> Result<String, anyhow::Error> {
+    let file = File::open(file_path).await?;
+    let mut hasher = Sha1::new();
+    let mut stream = FramedRead::new(file, BytesCodec::new());
+    while let Some(bytes) = stream.try_next().await? {
+        hasher.update(&bytes);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Content of a file which is uploaded while it is being read, e.g. while it is downloaded from Telegram.
+pub type FileStream = BoxStream<'static, Result<Bytes, anyhow::Error>>;
+
+/// Calculates SHA1 of the bytes passing through the streams it wraps, the same way as [`calculate_sha1`].
+#[derive(Clone, Default)]
+pub struct StreamHasher(Arc<Mutex<Sha1>>);
+
+impl StreamHasher {
+    /// Returns the stream, hashing every chunk read from it.
+    pub fn wrap<S, E>(&self, stream: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        let hasher = self.0.clone();
+        stream.inspect_ok(move |bytes| hasher.lock().unwrap().update(bytes))
+    }
+
+    /// Returns the hash of the bytes read so far.
+    pub fn sha1(&self) -> String {
+        hex::encode(self.0.lock().unwrap().clone().finalize())
+    }
+}
+
+/// Library the bot uploads the files to.
+///
+/// Methods return `Send` futures, so the bot can run them on any thread of the runtime.
+pub trait PhotoService: Send + Sync + 'static {
+    type Error: std::error::Error + From<anyhow::Error> + Send + Sync + 'static;
+
+    /// Checks that the library is available.
+    fn check_status(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Checks that the library can be accessed with the configured credentials.
+    fn check_access(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn upload_photo<P: AsRef<Path> + Send + Sync>(
+        &self,
+        path: P,
+    ) -> impl Future<Output = Result<PhotoUID, Self::Error>> + Send;
+
+    /// Uploads the file whose SHA1 hash is already known, e.g. because it has been calculated while the file
+    /// was downloaded. Libraries which need the hash of the file use it instead of reading the file once more.
+    fn upload_photo_with_hash<P: AsRef<Path> + Send + Sync>(
+        &self,
+        path: P,
+        _file_hash: &str,
+    ) -> impl Future<Output = Result<PhotoUID, Self::Error>> + Send {
+        self.upload_photo(path)
+    }
+
+    /// Returns true if [`PhotoService::upload_stream`] can upload a file without it being stored on disk first.
+    fn can_upload_stream(&self) -> bool {
+        false
+    }
+
+    /// Uploads the file read from the stream. The extension of the file name tells the type of the file.
+    fn upload_stream(
+        &self,
+        _file_name: &str,
+        _stream: FileStream,
+    ) -> impl Future<Output = Result<StreamedFile, Self::Error>> + Send {
+        async { Err(anyhow!("The library can not upload a stream.").into()) }
+    }
+
+    /// Returns the photo which has a file with the SHA1 hash, if the library already has one.
+    fn find_photo_by_hash(
+        &self,
+        file_hash: &str,
+    ) -> impl Future<Output = Result<Option<PhotoUID>, Self::Error>> + Send;
+
+    fn add_label(
+        &self,
+        photo_uid: &PhotoUID,
+        label: &str,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Removes the label from the photo. Does nothing if the photo does not have the label.
+    fn remove_label(
+        &self,
+        photo_uid: &PhotoUID,
+        label: &str,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Returns names of the labels of the photo.
+    fn list_photo_labels(
+        &self,
+        photo_uid: &PhotoUID,
+    ) -> impl Future<Output = Result<Vec<String>, Self::Error>> + Send;
+
+    /// Returns true if the time the photo has been taken at is known, for example from EXIF.
+    fn has_capture_date(
+        &self,
+        photo_uid: &PhotoUID,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    fn update_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        details: &PhotoDetails,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
 
-    async fn upload_photo<P: AsRef<Path>>(&self, path: P) -> Result<PhotoUID, Self::Error>;
+    /// Returns albums ordered by title.
+    fn list_albums(&self) -> impl Future<Output = Result<Vec<Album>, Self::Error>> + Send;
 
-    async fn add_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error>;
+    fn create_album(&self, title: &str) -> impl Future<Output = Result<Album, Self::Error>> + Send;
+
+    fn add_to_album(
+        &self,
+        album_uid: &str,
+        photo_uids: &[PhotoUID],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Moves the photos to the archive, where they are hidden but not deleted.
+    fn archive_photos(
+        &self,
+        photo_uids: &[PhotoUID],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Returns names of the labels known to the library.
+    fn list_labels(&self) -> impl Future<Output = Result<Vec<String>, Self::Error>> + Send;
+
+    /// Returns names of the people known to the library.
+    fn list_people(&self) -> impl Future<Output = Result<Vec<String>, Self::Error>> + Send;
+
+    /// Returns the names of the libraries the service stores the files at and whether each of them has the photo.
+    /// Empty for a service with a single library.
+    fn stored_at(&self, _photo_uid: &PhotoUID) -> Vec<(String, bool)> {
+        vec![]
+    }
 }
 
 #[derive(Error, Debug)]
@@ -48,6 +228,38 @@ pub enum PhotoPrismServiceError {
     CanNotFindPhotoByHash(String),
     #[error("Failed to add label {0} to file with uid {}", .photo_uid.0)]
     AddLabelFailed { label: String, photo_uid: PhotoUID },
+    #[error("Failed to remove label {label} from file with uid {}: {details}", .photo_uid.0)]
+    RemoveLabelFailed {
+        label: String,
+        photo_uid: PhotoUID,
+        details: String,
+    },
+    #[error("Failed to update file with uid {}: {details}", .photo_uid.0)]
+    UpdatePhotoFailed {
+        photo_uid: PhotoUID,
+        details: String,
+    },
+    #[error("Failed to get file with uid {}: {details}", .photo_uid.0)]
+    GetPhotoFailed {
+        photo_uid: PhotoUID,
+        details: String,
+    },
+    #[error("Failed to list albums: {0}")]
+    ListAlbumsFailed(String),
+    #[error("Failed to list labels: {0}")]
+    ListLabelsFailed(String),
+    #[error("Failed to list people: {0}")]
+    ListPeopleFailed(String),
+    #[error("Failed to create album {title}: {details}")]
+    CreateAlbumFailed { title: String, details: String },
+    #[error("Failed to add files to album with uid {album_uid}: {details}")]
+    AddToAlbumFailed { album_uid: String, details: String },
+    #[error("Failed to archive files: {0}")]
+    ArchiveFailed(String),
+    #[error("PhotoPrism server is not available: {0}")]
+    ServerNotAvailable(String),
+    #[error("PhotoPrism has rejected the session, the request should be sent again: {0}")]
+    SessionRejected(String),
     #[error("PhotoPrism API Error: {}", .err.to_string())]
     PhotoPrismAPIError {
         #[from]
@@ -57,6 +269,34 @@ pub enum PhotoPrismServiceError {
     Other(#[from] anyhow::Error),
 }
 
+impl PhotoPrismServiceError {
+    /// Name of the variant, used as a metric label.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            AuthenticationError(_) => "AuthenticationError",
+            AccessTokenIsMissing => "AccessTokenIsMissing",
+            UserIDIsMissing(_) => "UserIDIsMissing",
+            UploadFailed { .. } => "UploadFailed",
+            IndexingFailed { .. } => "IndexingFailed",
+            CanNotFindPhotoByHash(_) => "CanNotFindPhotoByHash",
+            AddLabelFailed { .. } => "AddLabelFailed",
+            RemoveLabelFailed { .. } => "RemoveLabelFailed",
+            UpdatePhotoFailed { .. } => "UpdatePhotoFailed",
+            GetPhotoFailed { .. } => "GetPhotoFailed",
+            ListAlbumsFailed(_) => "ListAlbumsFailed",
+            ListLabelsFailed(_) => "ListLabelsFailed",
+            ListPeopleFailed(_) => "ListPeopleFailed",
+            CreateAlbumFailed { .. } => "CreateAlbumFailed",
+            AddToAlbumFailed { .. } => "AddToAlbumFailed",
+            ArchiveFailed(_) => "ArchiveFailed",
+            ServerNotAvailable(_) => "ServerNotAvailable",
+            SessionRejected(_) => "SessionRejected",
+            PhotoPrismAPIError { .. } => "PhotoPrismAPIError",
+            Self::Other(_) => "Other",
+        }
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct Label {
@@ -64,6 +304,57 @@ struct Label {
     priority: i32,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AlbumResponse {
+    #[serde(rename = "UID")]
+    uid: String,
+    title: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PhotoResponse {
+    #[serde(default)]
+    taken_src: String,
+    #[serde(default)]
+    labels: Vec<PhotoLabelResponse>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PhotoLabelResponse {
+    #[serde(rename = "LabelID")]
+    label_id: u64,
+    uncertainty: i32,
+    label: NamedResponse,
+}
+
+impl PhotoLabelResponse {
+    // PhotoPrism keeps labels found by image classification after they have been removed,
+    // marking them as completely uncertain.
+    const REMOVED_UNCERTAINTY: i32 = 100;
+
+    fn is_removed(&self) -> bool {
+        self.uncertainty >= Self::REMOVED_UNCERTAINTY
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NamedResponse {
+    name: String,
+}
+
+impl From<AlbumResponse> for Album {
+    fn from(album: AlbumResponse) -> Self {
+        Self {
+            uid: album.uid,
+            title: album.title,
+        }
+    }
+}
+
 pub struct PhotoPrismPhotoService {
     photoprism_url: String,
     username: String,
@@ -78,6 +369,18 @@ pub struct PhotoPrismUser {
 }
 
 impl PhotoPrismPhotoService {
+    const MAX_ALBUMS: &'static str = "1000";
+
+    const MAX_LABELS: &'static str = "1000";
+
+    const MAX_PEOPLE: &'static str = "1000";
+
+    const MAX_RETRIES: usize = 5;
+
+    const RETRY_INITIAL_DELAY_MS: u64 = 500;
+
+    const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
     pub fn new(
         photoprism_url: String,
         username: String,
@@ -115,9 +418,70 @@ impl PhotoPrismPhotoService {
         format!("{}/api/v1{}", &self.photoprism_url, api_method)
     }
 
+    /// Uploads the file to the import folder of the user and returns the endpoint of the upload.
+    /// `file` names the file in the errors.
+    async fn upload(
+        &self,
+        file: &str,
+        extension: &str,
+        file_body: Body,
+    ) -> Result<String, PhotoPrismServiceError> {
+        let random_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 6);
+        //create the multipart form
+        let form = multipart::Form::new().part(
+            "files",
+            multipart::Part::stream(file_body).file_name(format!("unknown.{}", extension)),
+        );
+        let user_uid = &self.get_user().await?.uid;
+        let upload_http_endpoint =
+            self.endpoint(&format!("/users/{}/upload/{}", user_uid, random_token));
+        let upload_timer = metrics()
+            .photoprism_upload_duration
+            .with_label_values(&["upload"])
+            .start_timer();
+        let upload_file_resp = self
+            .send(self.client.post(&upload_http_endpoint).multipart(form))
+            .await?;
+        upload_timer.observe_duration();
+
+        if upload_file_resp.status() != StatusCode::OK {
+            return Err(UploadFailed {
+                file: file.to_owned(),
+                details: upload_file_resp.text().await?,
+            });
+        }
+        Ok(upload_http_endpoint)
+    }
+
+    /// Indexes the files of the upload. PhotoPrism skips files which it already has.
+    async fn index(
+        &self,
+        file: &str,
+        upload_http_endpoint: String,
+    ) -> Result<(), PhotoPrismServiceError> {
+        let album_json: serde_json::Value = serde_json::from_str(r#"{"albums": []}"#).unwrap();
+        let index_timer = metrics()
+            .photoprism_upload_duration
+            .with_label_values(&["index"])
+            .start_timer();
+        let process_upload_file_resp = self
+            .send(self.client.put(upload_http_endpoint).json(&album_json))
+            .await?;
+        index_timer.observe_duration();
+
+        if process_upload_file_resp.status() != StatusCode::OK {
+            return Err(IndexingFailed {
+                file: file.to_owned(),
+                details: process_upload_file_resp.text().await?,
+            });
+        }
+        Ok(())
+    }
+
     async fn authentication(&self) -> Result<PhotoPrismUser, PhotoPrismServiceError> {
         // TODO: Use oauth2 when it will be ready https://github.com/photoprism/photoprism/issues/3943
         let params = HashMap::from([("username", &self.username), ("password", &self.password)]);
+        metrics().photoprism_auth_refreshes.inc();
 
         let auth_resp = self
             .client
@@ -148,35 +512,222 @@ impl PhotoPrismPhotoService {
         Ok(PhotoPrismUser { access_token, uid })
     }
 
-    async fn calculate_sha1<P: AsRef<Path>>(file_path: P) -> Result<String, anyhow::Error> {
-        let file = File::open(file_path).await?;
-        let mut hasher = Sha1::new();
-        let mut stream = FramedRead::new(file, BytesCodec::new());
-        while let Some(bytes) = stream.try_next().await? {
-            hasher.update(&bytes);
-        }
-
-        Ok(hex::encode(hasher.finalize()))
-    }
-
+    /// Sends the request with the X-Auth-Token of the current session.
+    ///
+    /// If PhotoPrism rejects the token, for example because it has been restarted, the bot logs in again
+    /// and replays the request once. Server errors and connection failures are retried with exponential backoff.
+    /// A POST may have been applied before the server failed, so it is retried only if it has not been sent at all.
+    /// Requests with a streamed body, like file uploads, can not be replayed. If the token of such a request
+    /// is rejected, the session is dropped and [`PhotoPrismServiceError::SessionRejected`] is returned,
+    /// so the request can be sent again with a new session.
     pub async fn send(
         &self,
         request_builder: RequestBuilder,
     ) -> Result<Response, PhotoPrismServiceError> {
         // It is possible to use middleware for that, but for me, it does not make sense to use middleware for just one class.
         // It's better to have a separate method instead.
-        let user = self.get_user().await?;
-        let response = request_builder
-            .header("X-Auth-Token", &user.access_token)
+        let (client, request) = request_builder.build_split();
+        let request = request?;
+        let is_idempotent = [Method::GET, Method::PUT, Method::DELETE].contains(request.method());
+        let mut delays = Exponential::from_millis(Self::RETRY_INITIAL_DELAY_MS)
+            .map(jitter)
+            .take(Self::MAX_RETRIES);
+        let mut reauthenticated = false;
+        loop {
+            let user = match self.get_user().await {
+                Err(PhotoPrismAPIError { err }) if Self::is_transient(&err) => {
+                    match delays.next() {
+                        Some(delay) => {
+                            warn!(
+                                "Failed to log in to PhotoPrism: {}, retrying in {:?}.",
+                                err, delay
+                            );
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                        None => return Err(err.into()),
+                    }
+                }
+                user => user?,
+            };
+            let Some(mut attempt) = request.try_clone() else {
+                let mut request = request;
+                Self::authorize(&mut request, &user)?;
+                let response = client.execute(request).await?;
+                if matches!(
+                    response.status(),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                ) {
+                    // The body has been consumed, so the next attempt has to send it again.
+                    self.user_cache.invalidate(&()).await;
+                    return Err(SessionRejected(response.text().await?));
+                }
+                return Ok(response);
+            };
+            Self::authorize(&mut attempt, &user)?;
+            let delay = match client.execute(attempt).await {
+                Ok(response)
+                    if !reauthenticated
+                        && matches!(
+                            response.status(),
+                            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                        ) =>
+                {
+                    warn!(
+                        "PhotoPrism rejected the session of {}, logging in again.",
+                        self.username
+                    );
+                    self.user_cache.invalidate(&()).await;
+                    reauthenticated = true;
+                    continue;
+                }
+                Ok(response) if is_idempotent && response.status().is_server_error() => {
+                    match delays.next() {
+                        Some(delay) => {
+                            warn!(
+                                "PhotoPrism responded with {} to {}, retrying in {:?}.",
+                                response.status(),
+                                request.url(),
+                                delay
+                            );
+                            delay
+                        }
+                        None => return Ok(response),
+                    }
+                }
+                Err(err) if (is_idempotent && Self::is_transient(&err)) || err.is_connect() => {
+                    match delays.next() {
+                        Some(delay) => {
+                            warn!(
+                                "Failed to send {}: {}, retrying in {:?}.",
+                                request.url(),
+                                err,
+                                delay
+                            );
+                            delay
+                        }
+                        None => return Err(err.into()),
+                    }
+                }
+                result => return Ok(result?),
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn get_photo(
+        &self,
+        photo_uid: &PhotoUID,
+    ) -> Result<PhotoResponse, PhotoPrismServiceError> {
+        let get_photo_http_endpoint = self.endpoint(&format!("/photos/{}", photo_uid.0));
+        let get_photo_response = self.send(self.client.get(&get_photo_http_endpoint)).await?;
+
+        if get_photo_response.status() != StatusCode::OK {
+            return Err(GetPhotoFailed {
+                photo_uid: (*photo_uid).to_owned(),
+                details: get_photo_response.text().await?,
+            });
+        }
+
+        Ok(get_photo_response.json::<PhotoResponse>().await?)
+    }
+
+    fn is_transient(err: &reqwest::Error) -> bool {
+        err.is_connect() || err.is_timeout()
+    }
+
+    fn authorize(
+        request: &mut reqwest::Request,
+        user: &PhotoPrismUser,
+    ) -> Result<(), PhotoPrismServiceError> {
+        let access_token =
+            HeaderValue::from_str(&user.access_token).map_err(anyhow::Error::from)?;
+        request.headers_mut().insert("X-Auth-Token", access_token);
+        Ok(())
+    }
+}
+
+impl PhotoService for PhotoPrismPhotoService {
+    type Error = PhotoPrismServiceError;
+
+    /// Checks that the PhotoPrism server responds. Unlike other requests, it is neither authenticated nor retried.
+    async fn check_status(&self) -> Result<(), Self::Error> {
+        let status_response = self
+            .client
+            .get(self.endpoint("/status"))
+            .timeout(Self::STATUS_TIMEOUT)
             .send()
             .await?;
-        Ok(response)
+
+        if status_response.status() != StatusCode::OK {
+            return Err(ServerNotAvailable(status_response.text().await?));
+        }
+        Ok(())
+    }
+
+    async fn check_access(&self) -> Result<(), Self::Error> {
+        self.get_user().await?;
+        Ok(())
+    }
+
+    /// Hashes the file while it is uploaded, so the file is read only once.
+    /// Unlike a stream, the file can be read again, so it is uploaded once more if PhotoPrism has rejected the session.
+    async fn upload_photo<P: AsRef<Path> + Send + Sync>(
+        &self,
+        file_path: P,
+    ) -> Result<PhotoUID, Self::Error> {
+        let file_name = file_path.as_ref().to_str().unwrap();
+        let mut reauthenticated = false;
+        loop {
+            let file = File::open(&file_path).await.map_err(anyhow::Error::from)?;
+            let stream = FramedRead::new(file, BytesCodec::new())
+                .map_ok(BytesMut::freeze)
+                .map_err(anyhow::Error::from)
+                .boxed();
+            match self.upload_stream(file_name, stream).await {
+                Err(SessionRejected(_)) if !reauthenticated => reauthenticated = true,
+                result => return result.map(|streamed_file| streamed_file.photo_uid),
+            }
+        }
+    }
+
+    fn can_upload_stream(&self) -> bool {
+        true
     }
 
-    async fn search_photo_by_hash(
+    async fn upload_stream(
         &self,
-        file_hash: &String,
-    ) -> Result<Option<PhotoUID>, PhotoPrismServiceError> {
+        file_name: &str,
+        stream: FileStream,
+    ) -> Result<StreamedFile, Self::Error> {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|v| v.to_str())
+            .unwrap_or("unknown");
+        let hasher = StreamHasher::default();
+        let file_body = Body::wrap_stream(hasher.wrap(stream));
+        let upload_http_endpoint = self.upload(file_name, extension, file_body).await?;
+
+        // The hash is known once the file has been sent, so PhotoPrism is asked for the file before it is indexed.
+        let file_hash = hasher.sha1();
+        let known_photo_uid = self.find_photo_by_hash(&file_hash).await?;
+        self.index(file_name, upload_http_endpoint).await?;
+        let already_uploaded = known_photo_uid.is_some();
+        let photo_uid = match known_photo_uid {
+            Some(photo_uid) => photo_uid,
+            None => self
+                .find_photo_by_hash(&file_hash)
+                .await?
+                .ok_or_else(|| CanNotFindPhotoByHash(file_hash.clone()))?,
+        };
+        Ok(StreamedFile {
+            photo_uid,
+            sha1: file_hash,
+            already_uploaded,
+        })
+    }
+
+    async fn find_photo_by_hash(&self, file_hash: &str) -> Result<Option<PhotoUID>, Self::Error> {
         let search_by_hash = format!("quality:-100 hash:{}", file_hash);
         let search_params: Vec<(&str, &str)> = vec![
             ("q", search_by_hash.as_str()),
@@ -200,57 +751,6 @@ impl PhotoPrismPhotoService {
             .and_then(|v| v.as_str())
             .map(|v| PhotoUID(v.to_owned())))
     }
-}
-
-impl PhotoService for PhotoPrismPhotoService {
-    type Error = PhotoPrismServiceError;
-    async fn upload_photo<P: AsRef<Path>>(&self, file_path: P) -> Result<PhotoUID, Self::Error> {
-        let random_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 6);
-        let extension: &str = file_path
-            .as_ref()
-            .extension()
-            .and_then(|v| v.to_str())
-            .unwrap();
-        let file = File::open(&file_path).await.map_err(anyhow::Error::from)?;
-        let file_body: Body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
-        //create the multipart form
-        let form = multipart::Form::new().part(
-            "files",
-            multipart::Part::stream(file_body).file_name(format!("unknown.{}", extension)),
-        );
-        let user_uid = &self.get_user().await?.uid;
-        let upload_http_endpoint =
-            self.endpoint(&format!("/users/{}/upload/{}", user_uid, random_token));
-        let upload_file_resp = self
-            .send(self.client.post(&upload_http_endpoint).multipart(form))
-            .await?;
-
-        if upload_file_resp.status() != StatusCode::OK {
-            return Err(UploadFailed {
-                file: file_path.as_ref().to_str().unwrap().to_owned(),
-                details: upload_file_resp.text().await?,
-            });
-        }
-
-        let album_json: serde_json::Value = serde_json::from_str(r#"{"albums": []}"#).unwrap();
-        let process_upload_file_resp = self
-            .send(self.client.put(upload_http_endpoint).json(&album_json))
-            .await?;
-
-        if process_upload_file_resp.status() != StatusCode::OK {
-            return Err(IndexingFailed {
-                file: file_path.as_ref().to_str().unwrap().to_owned(),
-                details: process_upload_file_resp.text().await?,
-            });
-        }
-
-        let file_hash = Self::calculate_sha1(file_path).await?;
-
-        match self.search_photo_by_hash(&file_hash).await? {
-            Some(photo_uid) => Ok(photo_uid),
-            None => Err(CanNotFindPhotoByHash(file_hash)),
-        }
-    }
 
     async fn add_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
         let add_label_http_endpoint = self.endpoint(&format!("/photos/{}/label", photo_uid.0));
@@ -275,4 +775,215 @@ impl PhotoService for PhotoPrismPhotoService {
             Ok(())
         }
     }
+
+    async fn remove_label(&self, photo_uid: &PhotoUID, label: &str) -> Result<(), Self::Error> {
+        let photo = self.get_photo(photo_uid).await?;
+        let Some(photo_label) = photo
+            .labels
+            .iter()
+            .find(|l| !l.is_removed() && l.label.name == label)
+        else {
+            return Ok(());
+        };
+
+        let remove_label_http_endpoint = self.endpoint(&format!(
+            "/photos/{}/label/{}",
+            photo_uid.0, photo_label.label_id
+        ));
+        let remove_label_response = self
+            .send(self.client.delete(&remove_label_http_endpoint))
+            .await?;
+
+        if remove_label_response.status() != StatusCode::OK {
+            Err(RemoveLabelFailed {
+                label: label.to_owned(),
+                photo_uid: (*photo_uid).to_owned(),
+                details: remove_label_response.text().await?,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_photo_labels(&self, photo_uid: &PhotoUID) -> Result<Vec<String>, Self::Error> {
+        let photo = self.get_photo(photo_uid).await?;
+        Ok(photo
+            .labels
+            .into_iter()
+            .filter(|l| !l.is_removed())
+            .map(|l| l.label.name)
+            .collect())
+    }
+
+    async fn has_capture_date(&self, photo_uid: &PhotoUID) -> Result<bool, Self::Error> {
+        let photo = self.get_photo(photo_uid).await?;
+        Ok(CAPTURE_DATE_SOURCES.contains(&photo.taken_src.as_str()))
+    }
+
+    async fn update_photo(
+        &self,
+        photo_uid: &PhotoUID,
+        details: &PhotoDetails,
+    ) -> Result<(), Self::Error> {
+        let mut update_params = serde_json::Map::new();
+        if let Some(title) = &details.title {
+            update_params.insert("Title".to_owned(), title.as_str().into());
+            update_params.insert("TitleSrc".to_owned(), "manual".into());
+        }
+        if let Some(description) = &details.description {
+            update_params.insert("Description".to_owned(), description.as_str().into());
+            update_params.insert("DescriptionSrc".to_owned(), "manual".into());
+        }
+        if let Some(taken_at) = &details.taken_at {
+            let taken_at = taken_at.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+            update_params.insert("TakenAt".to_owned(), taken_at.as_str().into());
+            update_params.insert("TakenAtLocal".to_owned(), taken_at.into());
+            update_params.insert("TimeZone".to_owned(), "UTC".into());
+            update_params.insert("TakenSrc".to_owned(), "manual".into());
+        }
+
+        let update_photo_http_endpoint = self.endpoint(&format!("/photos/{}", photo_uid.0));
+        let update_photo_response = self
+            .send(
+                self.client
+                    .put(&update_photo_http_endpoint)
+                    .json(&update_params),
+            )
+            .await?;
+
+        if update_photo_response.status() != StatusCode::OK {
+            Err(UpdatePhotoFailed {
+                photo_uid: (*photo_uid).to_owned(),
+                details: update_photo_response.text().await?,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_albums(&self) -> Result<Vec<Album>, Self::Error> {
+        let list_albums_params: Vec<(&str, &str)> = vec![
+            ("type", "album"),
+            ("count", Self::MAX_ALBUMS),
+            ("order", "name"),
+        ];
+        let list_albums_response = self
+            .send(
+                self.client
+                    .get(self.endpoint("/albums"))
+                    .query(&list_albums_params),
+            )
+            .await?;
+
+        if list_albums_response.status() != StatusCode::OK {
+            return Err(ListAlbumsFailed(list_albums_response.text().await?));
+        }
+
+        let albums = list_albums_response.json::<Vec<AlbumResponse>>().await?;
+        Ok(albums.into_iter().map(Album::from).collect())
+    }
+
+    async fn create_album(&self, title: &str) -> Result<Album, Self::Error> {
+        let create_album_params = HashMap::from([("Title", title)]);
+        let create_album_response = self
+            .send(
+                self.client
+                    .post(self.endpoint("/albums"))
+                    .json(&create_album_params),
+            )
+            .await?;
+
+        if create_album_response.status() != StatusCode::OK {
+            return Err(CreateAlbumFailed {
+                title: title.to_owned(),
+                details: create_album_response.text().await?,
+            });
+        }
+
+        let album = create_album_response.json::<AlbumResponse>().await?;
+        Ok(album.into())
+    }
+
+    async fn add_to_album(
+        &self,
+        album_uid: &str,
+        photo_uids: &[PhotoUID],
+    ) -> Result<(), Self::Error> {
+        let add_to_album_http_endpoint = self.endpoint(&format!("/albums/{}/photos", album_uid));
+        let add_to_album_params = HashMap::from([("photos", photo_uids)]);
+        let add_to_album_response = self
+            .send(
+                self.client
+                    .post(&add_to_album_http_endpoint)
+                    .json(&add_to_album_params),
+            )
+            .await?;
+
+        if add_to_album_response.status() != StatusCode::OK {
+            Err(AddToAlbumFailed {
+                album_uid: album_uid.to_owned(),
+                details: add_to_album_response.text().await?,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn archive_photos(&self, photo_uids: &[PhotoUID]) -> Result<(), Self::Error> {
+        let archive_params = HashMap::from([("photos", photo_uids)]);
+        let archive_response = self
+            .send(
+                self.client
+                    .post(self.endpoint("/batch/photos/archive"))
+                    .json(&archive_params),
+            )
+            .await?;
+
+        if archive_response.status() != StatusCode::OK {
+            Err(ArchiveFailed(archive_response.text().await?))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_labels(&self) -> Result<Vec<String>, Self::Error> {
+        let list_labels_params: Vec<(&str, &str)> =
+            vec![("count", Self::MAX_LABELS), ("all", "true")];
+        let list_labels_response = self
+            .send(
+                self.client
+                    .get(self.endpoint("/labels"))
+                    .query(&list_labels_params),
+            )
+            .await?;
+
+        if list_labels_response.status() != StatusCode::OK {
+            return Err(ListLabelsFailed(list_labels_response.text().await?));
+        }
+
+        let labels = list_labels_response.json::<Vec<NamedResponse>>().await?;
+        Ok(labels.into_iter().map(|label| label.name).collect())
+    }
+
+    async fn list_people(&self) -> Result<Vec<String>, Self::Error> {
+        let list_people_params: Vec<(&str, &str)> = vec![
+            ("type", "person"),
+            ("count", Self::MAX_PEOPLE),
+            ("order", "name"),
+        ];
+        let list_people_response = self
+            .send(
+                self.client
+                    .get(self.endpoint("/subjects"))
+                    .query(&list_people_params),
+            )
+            .await?;
+
+        if list_people_response.status() != StatusCode::OK {
+            return Err(ListPeopleFailed(list_people_response.text().await?));
+        }
+
+        let people = list_people_response.json::<Vec<NamedResponse>>().await?;
+        Ok(people.into_iter().map(|person| person.name).collect())
+    }
 }
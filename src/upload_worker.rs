@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, warn};
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use teloxide::RequestError;
+
+use telegram2photoprism::{
+    metrics, CompositeServiceError, DirectoryServiceError, ImmichServiceError, Job,
+    PhotoPrismServiceError, PhotoService,
+};
+
+use crate::chats::Chats;
+use crate::{report_upload, upload_media, ApplicationContext, Bot, UploadTask};
+
+// Delay after the first failed attempt. It doubles after every next failure.
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(30);
+
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+// With the maximum delay, failed uploads are retried for about two days.
+const MAX_ATTEMPTS: u32 = 50;
+
+// How long the worker sleeps if the queue is empty, unless a job is added meanwhile.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Uploads the queued files one by one. Failed uploads are retried with exponential backoff,
+/// so files sent while PhotoPrism is not available are uploaded when it is back.
+pub async fn run_upload_worker<S: PhotoService>(
+    bot: Bot,
+    app_context: Arc<ApplicationContext>,
+    chats: Arc<Chats<S>>,
+) {
+    loop {
+        match app_context.upload_queue.next_due(Utc::now()).await {
+            Some(job) => {
+                let job_id = job.id;
+                if let Err(err) = process_upload_job(&bot, &app_context, &chats, job).await {
+                    error!("Failed to process upload {}: {:?}", job_id, err);
+                }
+            }
+            None => {
+                let timeout = app_context
+                    .upload_queue
+                    .next_attempt_at()
+                    .await
+                    .map_or(IDLE_TIMEOUT, |next_attempt_at| {
+                        (next_attempt_at - Utc::now()).to_std().unwrap_or_default()
+                    });
+                tokio::select! {
+                    _ = app_context.upload_queue_changed.notified() => {}
+                    _ = tokio::time::sleep(timeout) => {}
+                }
+            }
+        }
+    }
+}
+
+async fn process_upload_job<S: PhotoService>(
+    bot: &Bot,
+    app_context: &Arc<ApplicationContext>,
+    chats: &Chats<S>,
+    job: Job<UploadTask>,
+) -> Result<(), anyhow::Error> {
+    let task = &job.payload;
+    let chat_id = ChatId(task.chat_id);
+    let status_message_id = MessageId(task.status_message_id);
+    let Some(chat) = chats.get(chat_id) else {
+        warn!(
+            "Chat {} is not watched anymore, dropping upload {}.",
+            task.chat_id, job.id
+        );
+        app_context.upload_queue.remove(job.id).await?;
+        return Ok(());
+    };
+
+    let err = match upload_media(bot, app_context, &chat, &task.messages, status_message_id).await {
+        Ok(uploaded_files) => {
+            app_context.upload_queue.remove(job.id).await?;
+            report_upload(bot, app_context, &chat, status_message_id, &uploaded_files).await;
+            return Ok(());
+        }
+        Err(err) => err,
+    };
+    metrics().observe_error(&err);
+
+    let attempts = job.attempts + 1;
+    if is_permanent(&err) || attempts >= MAX_ATTEMPTS {
+        error!(
+            "Giving up upload {} after {} attempts: {:?}",
+            job.id, attempts, err
+        );
+        app_context.upload_queue.remove(job.id).await?;
+        bot.edit_message_text(
+            chat_id,
+            status_message_id,
+            t!("error-upload-failed", error = err.to_string()),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let delay = RETRY_INITIAL_DELAY
+        .saturating_mul(2u32.saturating_pow(job.attempts))
+        .min(RETRY_MAX_DELAY);
+    warn!(
+        "Upload {} failed (attempt {}), retrying in {:?}: {:?}",
+        job.id, attempts, delay, err
+    );
+    app_context
+        .upload_queue
+        .postpone(
+            job.id,
+            Utc::now() + chrono::Duration::from_std(delay)?,
+            err.to_string(),
+        )
+        .await?;
+    if job.attempts == 0 {
+        bot.edit_message_text(chat_id, status_message_id, t!("upload-delayed"))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Returns true if the upload would fail again, e.g. because the file has been deleted from Telegram.
+/// Network errors, server errors and rate limits are transient, as are errors which are not recognised.
+fn is_permanent(err: &anyhow::Error) -> bool {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<RequestError>() {
+            match err {
+                RequestError::Api(_)
+                | RequestError::MigrateToChatId(_)
+                | RequestError::InvalidJson { .. } => return true,
+                RequestError::RetryAfter(_) => return false,
+                RequestError::Network(_) | RequestError::Io(_) => {}
+            }
+        } else if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return err.status().is_some_and(is_permanent_status);
+        } else if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            return err.kind() == std::io::ErrorKind::NotFound;
+        } else if let Some(err) = cause.downcast_ref::<PhotoPrismServiceError>() {
+            match err {
+                PhotoPrismServiceError::CanNotFindPhotoByHash(_) => return true,
+                PhotoPrismServiceError::Other(err) => return is_permanent(err),
+                _ => {}
+            }
+        } else if let Some(err) = cause.downcast_ref::<ImmichServiceError>() {
+            match err {
+                ImmichServiceError::RequestFailed { status, .. } => {
+                    return is_permanent_status(*status)
+                }
+                ImmichServiceError::Other(err) => return is_permanent(err),
+                _ => {}
+            }
+        } else if let Some(err) = cause.downcast_ref::<DirectoryServiceError>() {
+            match err {
+                DirectoryServiceError::InvalidAlbumTitle(_)
+                | DirectoryServiceError::PhotoNotFound(_) => return true,
+                DirectoryServiceError::Other(err) => return is_permanent(err),
+                _ => {}
+            }
+        } else if let Some(err) = cause.downcast_ref::<CompositeServiceError>() {
+            match err {
+                CompositeServiceError::UnknownPhotoUID(_)
+                | CompositeServiceError::AlbumNotFound(_) => return true,
+                CompositeServiceError::Other(err) => return is_permanent(err),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+// Client errors other than rate limits mean the request itself is wrong.
+fn is_permanent_status(status: reqwest::StatusCode) -> bool {
+    status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+}
@@ -0,0 +1,73 @@
+use std::process::Command;
+
+use telegram2photoprism::ChatArgs;
+
+#[test]
+fn test_parse_chat_args() -> Result<(), anyhow::Error> {
+    let chat: ChatArgs =
+        "id=-100, tags=Luke | Leia||Han, album=Tatooine, labels=Family|, photoprism-username=luke"
+            .parse()?;
+    assert_eq!(
+        chat,
+        ChatArgs {
+            id: -100,
+            tags: Some(vec!["Luke".to_owned(), "Leia".to_owned(), "Han".to_owned()]),
+            album: Some("Tatooine".to_owned()),
+            labels: vec!["Family".to_owned()],
+            photoprism_username: Some("luke".to_owned()),
+            ..ChatArgs::default()
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_invalid_chat_args() {
+    let error = |s: &str| s.parse::<ChatArgs>().unwrap_err().to_string();
+    assert_eq!(error("id=1,planet=Hoth"), "Unknown chat setting 'planet'.");
+    assert_eq!(error("tags=Luke"), "Chat setting 'id' is missing.");
+    assert_eq!(error("id=Luke"), "Invalid chat id 'Luke'.");
+    assert_eq!(
+        error("id=1,album"),
+        "Expected key=value, got a chat setting without '='."
+    );
+    // The secrets are not repeated in the errors.
+    assert!(!error("photoprism-password=insecure").contains("insecure"));
+    assert!(!error("id=1,immich-api-key=se,cret").contains("cret"));
+}
+
+#[test]
+fn test_conflicting_photoprism_password() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_telegram2photoprism"))
+        .env_clear()
+        .arg("--working-dir")
+        .arg(working_dir.path())
+        .args([
+            "--photoprism-url",
+            "http://127.0.0.1:2342",
+            "--photoprism-username",
+            "admin",
+            "--chat",
+            "id=1,photoprism-password=insecure",
+            "--chat",
+            "id=2,photoprism-password=another",
+        ])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("another password"));
+    Ok(())
+}
+
+#[test]
+fn test_invalid_chat_hides_secrets() -> Result<(), anyhow::Error> {
+    let output = Command::new(env!("CARGO_BIN_EXE_telegram2photoprism"))
+        .env_clear()
+        .args(["--chat", "photoprism-password=insecure"])
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'id' is missing"));
+    assert!(!stderr.contains("insecure"));
+    Ok(())
+}
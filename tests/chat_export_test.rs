@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use chrono::{TimeZone, Utc};
+
+use telegram2photoprism::{read_chat_export, ExportedMessage};
+
+const RESULT_JSON: &str = r##"{
+  "name": "Family",
+  "type": "private_group",
+  "id": 4148908551,
+  "messages": [
+    {
+      "id": 12,
+      "type": "message",
+      "date": "2021-06-02T18:20:00",
+      "date_unixtime": "1622658000",
+      "from": "Leia",
+      "file": "video_files/IMG_0042.MOV",
+      "media_type": "video_file",
+      "mime_type": "video/quicktime",
+      "text": "",
+      "text_entities": []
+    },
+    {
+      "id": 10,
+      "type": "message",
+      "date": "2021-06-01T10:00:00",
+      "from": "Luke",
+      "photo": "photos/photo_1@01-06-2021_10-00-00.jpg",
+      "width": 1280,
+      "height": 960,
+      "text": ["Tatooine ", {"type": "hashtag", "text": "#Han"}],
+      "text_entities": [
+        {"type": "plain", "text": "Tatooine "},
+        {"type": "hashtag", "text": "#Han"}
+      ]
+    },
+    {
+      "id": 11,
+      "type": "service",
+      "date": "2021-06-01T11:00:00",
+      "actor": "Luke",
+      "action": "invite_members",
+      "text": "",
+      "text_entities": []
+    },
+    {
+      "id": 13,
+      "type": "message",
+      "date": "2021-06-03T09:00:00",
+      "from": "Han",
+      "file": "files/plans.pdf",
+      "mime_type": "application/pdf",
+      "text": "",
+      "text_entities": []
+    }
+  ]
+}"##;
+
+#[tokio::test]
+async fn test_read_chat_export() -> Result<(), anyhow::Error> {
+    let export_dir = tempfile::tempdir()?;
+    tokio::fs::write(export_dir.path().join("result.json"), RESULT_JSON).await?;
+
+    let messages = read_chat_export(export_dir.path()).await?;
+
+    assert_eq!(
+        messages,
+        vec![
+            ExportedMessage {
+                id: 10,
+                date: None,
+                file: PathBuf::from("photos/photo_1@01-06-2021_10-00-00.jpg"),
+                text: "Tatooine ".to_owned(),
+                hashtags: vec!["Han".to_owned()],
+            },
+            ExportedMessage {
+                id: 12,
+                date: Utc.timestamp_opt(1622658000, 0).single(),
+                file: PathBuf::from("video_files/IMG_0042.MOV"),
+                text: "".to_owned(),
+                hashtags: vec![],
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_chat_export_without_result_json() -> Result<(), anyhow::Error> {
+    let export_dir = tempfile::tempdir()?;
+    assert!(read_chat_export(export_dir.path()).await.is_err());
+    Ok(())
+}
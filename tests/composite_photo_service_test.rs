@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use telegram2photoprism::{
+    calculate_sha1, CompositePhotoService, Destination, DirectoryPhotoService, ImmichPhotoService,
+    PhotoService, PhotoUID,
+};
+
+const PHOTO_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/resources/tests/low_quality_photo.jpg"
+);
+
+#[tokio::test]
+async fn test_upload_to_every_target() -> Result<(), anyhow::Error> {
+    let library_dir = tempfile::tempdir()?;
+    let backup_dir = tempfile::tempdir()?;
+    let library = Arc::new(DirectoryPhotoService::new(library_dir.path().to_path_buf()));
+    let backup = Arc::new(DirectoryPhotoService::new(backup_dir.path().to_path_buf()));
+    let sha1 = calculate_sha1(PHOTO_PATH).await?;
+
+    // The photo is already in the library, so only the backup misses it.
+    library.upload_photo(PHOTO_PATH).await?;
+    let photo_service = CompositePhotoService::new(vec![
+        (
+            "library".to_owned(),
+            Destination::Directory(library.clone()),
+        ),
+        ("backup".to_owned(), Destination::Directory(backup.clone())),
+    ]);
+    assert_eq!(photo_service.find_photo_by_hash(&sha1).await?, None);
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    assert_eq!(
+        photo_service.stored_at(&photo_uid),
+        vec![("library".to_owned(), true), ("backup".to_owned(), true)]
+    );
+    assert_eq!(
+        photo_service.find_photo_by_hash(&sha1).await?,
+        Some(photo_uid.clone())
+    );
+
+    photo_service.add_label(&photo_uid, "Luke").await?;
+    let album = photo_service.create_album("Tatooine").await?;
+    photo_service
+        .add_to_album(&album.uid, std::slice::from_ref(&photo_uid))
+        .await?;
+    for (service, dir) in [(&library, &library_dir), (&backup, &backup_dir)] {
+        let uid = service.find_photo_by_hash(&sha1).await?.unwrap();
+        assert_eq!(
+            service.list_photo_labels(&uid).await?,
+            vec!["Luke".to_owned()]
+        );
+        assert!(dir
+            .path()
+            .join(format!("Albums/Tatooine/{}.jpg", sha1))
+            .is_file());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_with_failed_target() -> Result<(), anyhow::Error> {
+    // Nothing listens at the port, so every request to the server fails.
+    let server = Arc::new(ImmichPhotoService::new(
+        "http://127.0.0.1:1".to_owned(),
+        "api-key".to_owned(),
+    ));
+    let library_dir = tempfile::tempdir()?;
+    let library = Arc::new(DirectoryPhotoService::new(library_dir.path().to_path_buf()));
+    let photo_service = CompositePhotoService::new(vec![
+        ("server".to_owned(), Destination::Immich(server)),
+        ("library".to_owned(), Destination::Directory(library)),
+    ]);
+    let sha1 = calculate_sha1(PHOTO_PATH).await?;
+    assert_eq!(photo_service.find_photo_by_hash(&sha1).await?, None);
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    assert_eq!(
+        photo_service.stored_at(&photo_uid),
+        vec![("server".to_owned(), false), ("library".to_owned(), true)]
+    );
+    assert_eq!(photo_service.find_photo_by_hash(&sha1).await?, None);
+    photo_service.add_label(&photo_uid, "Han").await?;
+    assert_eq!(
+        photo_service.list_photo_labels(&photo_uid).await?,
+        vec!["Han".to_owned()]
+    );
+    assert!(!photo_service.has_capture_date(&photo_uid).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_uid_without_target_names() -> Result<(), anyhow::Error> {
+    let library_dir = tempfile::tempdir()?;
+    let backup_dir = tempfile::tempdir()?;
+    let library = Arc::new(DirectoryPhotoService::new(library_dir.path().to_path_buf()));
+    let backup = Arc::new(DirectoryPhotoService::new(backup_dir.path().to_path_buf()));
+    // The UID has been recorded when the library was the only backend.
+    let legacy_uid = library.upload_photo(PHOTO_PATH).await?;
+    let photo_service = CompositePhotoService::new(vec![
+        (
+            "library".to_owned(),
+            Destination::Directory(library.clone()),
+        ),
+        ("backup".to_owned(), Destination::Directory(backup)),
+    ]);
+
+    assert_eq!(
+        photo_service.stored_at(&legacy_uid),
+        vec![("library".to_owned(), true), ("backup".to_owned(), false)]
+    );
+    photo_service.add_label(&legacy_uid, "Chewie").await?;
+    assert_eq!(
+        library.list_photo_labels(&legacy_uid).await?,
+        vec!["Chewie".to_owned()]
+    );
+
+    let unknown_uid = PhotoUID("archive=123".to_owned());
+    assert!(photo_service
+        .add_label(&unknown_uid, "Chewie")
+        .await
+        .is_err());
+    assert!(photo_service.list_photo_labels(&unknown_uid).await.is_err());
+    Ok(())
+}
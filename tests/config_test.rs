@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use axum::routing::get;
+use axum::Router;
+
+// Refuses connections, so the health check fails if the bot takes this address.
+const UNUSED_ADDRESS: &str = "127.0.0.1:1";
+
+/// Starts a server which answers /readyz like a ready bot and returns its address.
+async fn start_ready_bot() -> SocketAddr {
+    let router = Router::new().route("/readyz", get(|| async { "OK" }));
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let address = server.local_addr();
+    tokio::spawn(server);
+    address
+}
+
+/// Runs the health check of the bot with the config file and the extra options.
+fn healthcheck(config: &str, args: &[&str], envs: &[(&str, &str)]) -> Output {
+    let config_dir = tempfile::tempdir().unwrap();
+    let config_path = config_dir.path().join("config.toml");
+    std::fs::write(&config_path, config).unwrap();
+    run(&config_path, args, envs)
+}
+
+fn run(config_path: &Path, args: &[&str], envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_telegram2photoprism"))
+        .env_clear()
+        .envs(envs.iter().copied())
+        .arg("--config")
+        .arg(config_path)
+        .args(args)
+        .arg("healthcheck")
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_option_from_config_file() {
+    let address = start_ready_bot().await;
+    let output = healthcheck(&format!("listen-address = \"{}\"", address), &[], &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_command_line_takes_precedence_over_config_file() {
+    let address = start_ready_bot().await;
+    let output = healthcheck(
+        &format!("listen-address = \"{}\"", UNUSED_ADDRESS),
+        &["--listen-address", &address.to_string()],
+        &[],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_environment_takes_precedence_over_config_file() {
+    let address = start_ready_bot().await;
+    let output = healthcheck(
+        &format!("listen-address = \"{}\"", UNUSED_ADDRESS),
+        &[],
+        &[("TELEGRAM2PHOTOPRISM_LISTEN_ADDRESS", &address.to_string())],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_unknown_key() {
+    let output = healthcheck("listen-adress = \"127.0.0.1:9090\"", &[], &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("unknown field `listen-adress`"));
+}
+
+#[test]
+fn test_invalid_values() {
+    let output = healthcheck("locale = \"de\"", &[], &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("unsupported value 'de' for key `locale`"));
+
+    let output = healthcheck("tags-page-size = 100", &[], &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("value 100 for key `tags-page-size` is not in 1..=90"));
+
+    let output = healthcheck("backend = []", &[], &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("key `backend` must list at least one backend"));
+}
+
+#[test]
+fn test_missing_config_file() {
+    let output = run(Path::new("/nonexistent/config.toml"), &[], &[]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to read config file"));
+}
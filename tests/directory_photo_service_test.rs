@@ -0,0 +1,118 @@
+use chrono::{TimeZone, Utc};
+
+use telegram2photoprism::{calculate_sha1, DirectoryPhotoService, PhotoDetails, PhotoService};
+
+const PHOTO_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/resources/tests/low_quality_photo.jpg"
+);
+
+#[tokio::test]
+async fn test_upload_photo_with_labels_and_date() -> Result<(), anyhow::Error> {
+    let library_dir = tempfile::tempdir()?;
+    let photo_service = DirectoryPhotoService::new(library_dir.path().to_path_buf());
+    photo_service.check_access().await?;
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    let sha1 = calculate_sha1(PHOTO_PATH).await?;
+    assert_eq!(photo_uid.0, sha1);
+    assert_eq!(
+        photo_service.find_photo_by_hash(&sha1).await?,
+        Some(photo_uid.clone())
+    );
+    assert!(!photo_service.has_capture_date(&photo_uid).await?);
+
+    photo_service.add_label(&photo_uid, "Luke & Leia").await?;
+    photo_service.add_label(&photo_uid, "Han").await?;
+    photo_service.remove_label(&photo_uid, "Han").await?;
+    let taken_at = Utc.with_ymd_and_hms(2019, 5, 4, 12, 0, 0).unwrap();
+    photo_service
+        .update_photo(
+            &photo_uid,
+            &PhotoDetails {
+                description: Some("May the 4th".to_owned()),
+                taken_at: Some(taken_at),
+                ..PhotoDetails::default()
+            },
+        )
+        .await?;
+
+    let photo_path = library_dir.path().join(format!("2019/05/{}.jpg", sha1));
+    assert!(photo_path.is_file());
+    let sidecar = std::fs::read_to_string(photo_path.with_extension("xmp"))?;
+    assert!(sidecar.contains("<rdf:li>Luke &amp; Leia</rdf:li>"));
+    assert!(sidecar.contains("May the 4th"));
+    assert!(sidecar.contains("2019-05-04T12:00:00Z"));
+
+    // A new service finds the files and their labels in the directory.
+    let photo_service = DirectoryPhotoService::new(library_dir.path().to_path_buf());
+    assert_eq!(
+        photo_service.find_photo_by_hash(&sha1).await?,
+        Some(photo_uid.clone())
+    );
+    assert!(photo_service.has_capture_date(&photo_uid).await?);
+    assert_eq!(
+        photo_service.list_photo_labels(&photo_uid).await?,
+        vec!["Luke & Leia".to_owned()]
+    );
+    assert_eq!(
+        photo_service.list_labels().await?,
+        vec!["Luke & Leia".to_owned()]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_albums_and_archive() -> Result<(), anyhow::Error> {
+    let library_dir = tempfile::tempdir()?;
+    let photo_service = DirectoryPhotoService::new(library_dir.path().to_path_buf());
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+
+    let album = photo_service.create_album("Tatooine").await?;
+    photo_service.create_album("Hoth").await?;
+    assert!(photo_service.create_album("../Alderaan").await.is_err());
+    photo_service
+        .add_to_album(&album.uid, std::slice::from_ref(&photo_uid))
+        .await?;
+    let titles: Vec<String> = photo_service
+        .list_albums()
+        .await?
+        .into_iter()
+        .map(|album| album.title)
+        .collect();
+    assert_eq!(titles, vec!["Hoth".to_owned(), "Tatooine".to_owned()]);
+    assert!(library_dir
+        .path()
+        .join(format!("Albums/Tatooine/{}.jpg", photo_uid.0))
+        .is_file());
+
+    photo_service
+        .archive_photos(std::slice::from_ref(&photo_uid))
+        .await?;
+    assert_eq!(photo_service.find_photo_by_hash(&photo_uid.0).await?, None);
+    let photo_service = DirectoryPhotoService::new(library_dir.path().to_path_buf());
+    assert_eq!(photo_service.find_photo_by_hash(&photo_uid.0).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_photo_with_known_hash() -> Result<(), anyhow::Error> {
+    let library_dir = tempfile::tempdir()?;
+    let photo_service = DirectoryPhotoService::new(library_dir.path().to_path_buf());
+    let other_library_dir = tempfile::tempdir()?;
+    let other_photo_service = DirectoryPhotoService::new(other_library_dir.path().to_path_buf());
+
+    let sha1 = calculate_sha1(PHOTO_PATH).await?;
+    let photo_uid = photo_service
+        .upload_photo_with_hash(PHOTO_PATH, &sha1)
+        .await?;
+    assert_eq!(
+        photo_uid,
+        other_photo_service.upload_photo(PHOTO_PATH).await?
+    );
+    assert_eq!(
+        photo_service.find_photo_by_hash(&sha1).await?,
+        Some(photo_uid)
+    );
+    Ok(())
+}
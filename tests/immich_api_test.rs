@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+
+use telegram2photoprism::{ImmichPhotoService, PhotoDetails, PhotoService, PhotoUID};
+
+const API_KEY: &str = "insecure";
+
+const PHOTO_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/resources/tests/low_quality_photo.jpg"
+);
+
+/// Keeps just enough state to answer the requests of the bot like Immich does.
+#[derive(Default)]
+struct ImmichStandIn {
+    // Asset IDs by checksum, for the duplicate check.
+    checksums: HashMap<String, String>,
+    uploads: usize,
+    // Tag names by asset ID.
+    tags: HashMap<String, Vec<String>>,
+    descriptions: HashMap<String, String>,
+    // ID, name and asset IDs of every album.
+    albums: Vec<(String, String, Vec<String>)>,
+}
+
+type SharedStandIn = Arc<Mutex<ImmichStandIn>>;
+
+fn authorized(headers: &HeaderMap) -> bool {
+    headers.get("x-api-key").is_some_and(|key| key == API_KEY)
+}
+
+fn ids(body: &Value) -> Vec<String> {
+    body["ids"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_str().map(str::to_owned))
+        .collect()
+}
+
+async fn ping() -> Json<Value> {
+    Json(json!({"res": "pong"}))
+}
+
+async fn get_user(headers: HeaderMap) -> (StatusCode, Json<Value>) {
+    match authorized(&headers) {
+        true => (StatusCode::OK, Json(json!({"id": "user-1"}))),
+        false => (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"message": "Invalid API key"})),
+        ),
+    }
+}
+
+async fn upload_asset(
+    State(stand_in): State<SharedStandIn>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<Value>) {
+    let body = String::from_utf8_lossy(&body);
+    if !authorized(&headers) || !body.contains("name=\"assetData\"") {
+        return (StatusCode::BAD_REQUEST, Json(json!({})));
+    }
+    let mut stand_in = stand_in.lock().unwrap();
+    stand_in.uploads += 1;
+    let id = format!("asset-{}", stand_in.uploads);
+    (
+        StatusCode::CREATED,
+        Json(json!({"id": id, "status": "created"})),
+    )
+}
+
+async fn check_upload(
+    State(stand_in): State<SharedStandIn>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let stand_in = stand_in.lock().unwrap();
+    let asset = &body["assets"][0];
+    let checksum = asset["checksum"].as_str().unwrap_or_default();
+    let result = match stand_in.checksums.get(checksum) {
+        Some(asset_id) => json!({
+            "id": asset["id"],
+            "action": "reject",
+            "reason": "duplicate",
+            "assetId": asset_id,
+        }),
+        None => json!({"id": asset["id"], "action": "accept"}),
+    };
+    Json(json!({ "results": [result] }))
+}
+
+fn tag_json(name: &str) -> Value {
+    json!({"id": format!("tag-{}", name), "name": name, "value": name})
+}
+
+async fn get_asset(State(stand_in): State<SharedStandIn>, Path(id): Path<String>) -> Json<Value> {
+    let stand_in = stand_in.lock().unwrap();
+    let tags: Vec<Value> = stand_in
+        .tags
+        .get(&id)
+        .into_iter()
+        .flatten()
+        .map(|name| tag_json(name))
+        .collect();
+    Json(json!({"id": id, "tags": tags, "exifInfo": {"dateTimeOriginal": null}}))
+}
+
+async fn update_asset(
+    State(stand_in): State<SharedStandIn>,
+    Path(id): Path<String>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let mut stand_in = stand_in.lock().unwrap();
+    if let Some(description) = body["description"].as_str() {
+        stand_in
+            .descriptions
+            .insert(id.clone(), description.to_owned());
+    }
+    Json(json!({ "id": id }))
+}
+
+async fn upsert_tags(Json(body): Json<Value>) -> Json<Value> {
+    let tags: Vec<Value> = body["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.as_str().map(tag_json))
+        .collect();
+    Json(Value::Array(tags))
+}
+
+async fn tag_assets(
+    State(stand_in): State<SharedStandIn>,
+    Path(tag_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let mut stand_in = stand_in.lock().unwrap();
+    let name = tag_id.trim_start_matches("tag-");
+    for id in ids(&body) {
+        stand_in.tags.entry(id).or_default().push(name.to_owned());
+    }
+    Json(json!([]))
+}
+
+async fn untag_assets(
+    State(stand_in): State<SharedStandIn>,
+    Path(tag_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let mut stand_in = stand_in.lock().unwrap();
+    let name = tag_id.trim_start_matches("tag-");
+    for id in ids(&body) {
+        stand_in
+            .tags
+            .entry(id)
+            .or_default()
+            .retain(|tag| tag != name);
+    }
+    Json(json!([]))
+}
+
+async fn list_albums(State(stand_in): State<SharedStandIn>) -> Json<Value> {
+    let stand_in = stand_in.lock().unwrap();
+    let albums: Vec<Value> = stand_in
+        .albums
+        .iter()
+        .map(|(id, name, _)| json!({"id": id, "albumName": name}))
+        .collect();
+    Json(Value::Array(albums))
+}
+
+async fn create_album(
+    State(stand_in): State<SharedStandIn>,
+    Json(body): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    let mut stand_in = stand_in.lock().unwrap();
+    let id = format!("album-{}", stand_in.albums.len() + 1);
+    let name = body["albumName"].as_str().unwrap_or_default().to_owned();
+    stand_in.albums.push((id.clone(), name.clone(), vec![]));
+    (
+        StatusCode::CREATED,
+        Json(json!({"id": id, "albumName": name})),
+    )
+}
+
+async fn add_album_assets(
+    State(stand_in): State<SharedStandIn>,
+    Path(album_id): Path<String>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    let mut stand_in = stand_in.lock().unwrap();
+    if let Some((_, _, assets)) = stand_in
+        .albums
+        .iter_mut()
+        .find(|(id, _, _)| *id == album_id)
+    {
+        assets.extend(ids(&body));
+    }
+    Json(json!([]))
+}
+
+/// Starts the stand-in at a random port and returns its URL.
+async fn start_stand_in(stand_in: SharedStandIn) -> String {
+    let router = Router::new()
+        .route("/api/server/ping", get(ping))
+        .route("/api/users/me", get(get_user))
+        .route("/api/assets", post(upload_asset))
+        .route("/api/assets/bulk-upload-check", post(check_upload))
+        .route("/api/assets/:id", get(get_asset).put(update_asset))
+        .route("/api/tags", put(upsert_tags))
+        .route("/api/tags/:id/assets", put(tag_assets).delete(untag_assets))
+        .route("/api/albums", get(list_albums).post(create_album))
+        .route("/api/albums/:id/assets", put(add_album_assets))
+        .with_state(stand_in);
+
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let address = server.local_addr();
+    tokio::spawn(server);
+    format!("http://{}", address)
+}
+
+#[tokio::test]
+async fn test_upload_photo_add_labels() -> Result<(), anyhow::Error> {
+    let stand_in = SharedStandIn::default();
+    stand_in
+        .lock()
+        .unwrap()
+        .checksums
+        .insert("known-sha1".to_owned(), "asset-known".to_owned());
+    let immich_url = start_stand_in(stand_in.clone()).await;
+    let photo_service = ImmichPhotoService::new(immich_url, API_KEY.to_owned());
+    photo_service.check_status().await?;
+    photo_service.check_access().await?;
+
+    assert_eq!(
+        photo_service.find_photo_by_hash("known-sha1").await?,
+        Some(PhotoUID("asset-known".to_owned()))
+    );
+    assert_eq!(
+        photo_service.find_photo_by_hash("unknown-sha1").await?,
+        None
+    );
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    assert_eq!(photo_uid, PhotoUID("asset-1".to_owned()));
+    photo_service.add_label(&photo_uid, "Luke").await?;
+    photo_service.add_label(&photo_uid, "Han").await?;
+    photo_service.remove_label(&photo_uid, "Han").await?;
+    assert_eq!(
+        photo_service.list_photo_labels(&photo_uid).await?,
+        vec!["Luke".to_owned()]
+    );
+    assert!(!photo_service.has_capture_date(&photo_uid).await?);
+
+    photo_service
+        .update_photo(
+            &photo_uid,
+            &PhotoDetails {
+                description: Some("May the 4th".to_owned()),
+                ..PhotoDetails::default()
+            },
+        )
+        .await?;
+    assert_eq!(
+        stand_in.lock().unwrap().descriptions.get("asset-1"),
+        Some(&"May the 4th".to_owned())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_photo_to_album() -> Result<(), anyhow::Error> {
+    let stand_in = SharedStandIn::default();
+    let immich_url = start_stand_in(stand_in.clone()).await;
+    let photo_service = ImmichPhotoService::new(immich_url, API_KEY.to_owned());
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    let album = photo_service.create_album("Tatooine").await?;
+    photo_service
+        .add_to_album(&album.uid, std::slice::from_ref(&photo_uid))
+        .await?;
+
+    let albums = photo_service.list_albums().await?;
+    assert_eq!(albums.len(), 1);
+    assert_eq!(albums[0].title, "Tatooine");
+    assert_eq!(
+        stand_in.lock().unwrap().albums[0].2,
+        vec![photo_uid.0.to_owned()]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wrong_api_key() -> Result<(), anyhow::Error> {
+    let immich_url = start_stand_in(SharedStandIn::default()).await;
+    let photo_service = ImmichPhotoService::new(immich_url, "wrong".to_owned());
+    photo_service.check_status().await?;
+    assert!(photo_service.check_access().await.is_err());
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::process::Command;
+
+const PHOTO_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/resources/tests/low_quality_photo.jpg"
+);
+
+const RESULT_JSON: &str = r##"{
+  "name": "Family",
+  "type": "private_group",
+  "id": 4148908551,
+  "messages": [
+    {
+      "id": 10,
+      "type": "message",
+      "date": "2021-06-01T10:00:00",
+      "from": "Luke",
+      "photo": "photos/photo_1@01-06-2021_10-00-00.jpg",
+      "text": "",
+      "text_entities": []
+    }
+  ]
+}"##;
+
+#[test]
+fn test_import_reports_summary() -> Result<(), anyhow::Error> {
+    let export_dir = tempfile::tempdir()?;
+    std::fs::write(export_dir.path().join("result.json"), RESULT_JSON)?;
+    std::fs::create_dir(export_dir.path().join("photos"))?;
+    std::fs::copy(
+        PHOTO_PATH,
+        export_dir
+            .path()
+            .join("photos/photo_1@01-06-2021_10-00-00.jpg"),
+    )?;
+    let library_dir = tempfile::tempdir()?;
+    let working_dir = tempfile::tempdir()?;
+
+    // RUST_LOG is not set, as with a default installation.
+    let output = Command::new(env!("CARGO_BIN_EXE_telegram2photoprism"))
+        .env_clear()
+        .arg("--working-dir")
+        .arg(working_dir.path())
+        .args(["--telegram-chat-id=-4148908551", "--backend", "directory"])
+        .arg("--directory")
+        .arg(library_dir.path())
+        .arg("import")
+        .arg(export_dir.path())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Imported 1 files"), "{}", stderr);
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use chrono::{Duration, Utc};
+
+use telegram2photoprism::JobQueue;
+
+#[tokio::test]
+async fn test_jobs_survive_reopening() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let path = working_dir.path().join("uploads.jsonl");
+
+    let queue: JobQueue<String> = JobQueue::open(&path).await?;
+    let first = queue.push("first".to_owned()).await?;
+    let second = queue.push("second".to_owned()).await?;
+    let third = queue.push("third".to_owned()).await?;
+    queue.remove(first.id).await?;
+    queue
+        .postpone(
+            second.id,
+            Utc::now() + Duration::hours(1),
+            "PhotoPrism is down".to_owned(),
+        )
+        .await?;
+    drop(queue);
+
+    let queue: JobQueue<String> = JobQueue::open(&path).await?;
+    let jobs = queue.jobs().await;
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0].payload, "second");
+    assert_eq!(jobs[0].attempts, 1);
+    assert_eq!(jobs[0].last_error.as_deref(), Some("PhotoPrism is down"));
+
+    // The postponed job is not due yet.
+    let due = queue.next_due(Utc::now()).await.unwrap();
+    assert_eq!(due.id, third.id);
+    queue.remove(third.id).await?;
+    assert!(queue.next_due(Utc::now()).await.is_none());
+    assert_eq!(queue.next_attempt_at().await, Some(jobs[0].next_attempt_at));
+
+    // Ids of finished jobs are not reused.
+    let fourth = queue.push("fourth".to_owned()).await?;
+    assert!(fourth.id > third.id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_incomplete_last_line_is_skipped() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let path = working_dir.path().join("uploads.jsonl");
+
+    let queue: JobQueue<String> = JobQueue::open(&path).await?;
+    queue.push("first".to_owned()).await?;
+    drop(queue);
+    // The bot has crashed while it was writing the second job.
+    let mut journal = std::fs::read_to_string(&path)?;
+    journal.push_str(r#"{"id":2,"job":{"id":2,"crea"#);
+    std::fs::write(&path, &journal)?;
+
+    let queue: JobQueue<String> = JobQueue::open(&path).await?;
+    let jobs = queue.jobs().await;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].payload, "first");
+    queue.push("second".to_owned()).await?;
+    drop(queue);
+
+    let queue: JobQueue<String> = JobQueue::open(&path).await?;
+    assert_eq!(queue.jobs().await.len(), 2);
+    drop(queue);
+
+    // A broken line in the middle of the journal is not skipped.
+    let journal = std::fs::read_to_string(&path)?;
+    std::fs::write(&path, format!("{{\n{}", journal))?;
+    assert!(JobQueue::<String>::open(&path).await.is_err());
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use chrono::Utc;
+
+use telegram2photoprism::{MessageStore, PhotoUID, UploadRecord};
+
+fn upload_record(message_id: i32, photo_uid: &str) -> UploadRecord {
+    UploadRecord {
+        chat_id: -4148908551,
+        message_id,
+        status_message_id: Some(100),
+        file_unique_id: format!("unique-{}", message_id),
+        sha1: format!("sha1-{}", message_id),
+        photo_uid: PhotoUID(photo_uid.to_owned()),
+        uploaded_at: Utc::now(),
+        labels: vec!["Han".to_owned()],
+    }
+}
+
+#[tokio::test]
+async fn test_records_survive_reopening() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let path = working_dir.path().join("messages.jsonl");
+
+    let store = MessageStore::open(&path).await?;
+    store.insert(upload_record(1, "pq1")).await?;
+    store.insert(upload_record(2, "pq2")).await?;
+    store
+        .add_labels(
+            &PhotoUID("pq1".to_owned()),
+            &["Luke".to_owned(), "Han".to_owned()],
+        )
+        .await?;
+    store
+        .add_labels(&PhotoUID("pq1".to_owned()), &["Vader".to_owned()])
+        .await?;
+    store
+        .remove_labels(&PhotoUID("pq1".to_owned()), &["Vader".to_owned()])
+        .await?;
+    drop(store);
+
+    let store = MessageStore::open(&path).await?;
+    let record = store.find_by_message(-4148908551, 1).await.unwrap();
+    assert_eq!(record.photo_uid, PhotoUID("pq1".to_owned()));
+    assert_eq!(record.labels, vec!["Han".to_owned(), "Luke".to_owned()]);
+    assert_eq!(
+        store.find_by_status_message(-4148908551, 100).await.len(),
+        2
+    );
+    assert_eq!(store.find_by_sha1("sha1-2").await.unwrap().message_id, 2);
+    assert!(store.find_by_message(-4148908551, 3).await.is_none());
+
+    // The journal is compacted to one line per message.
+    assert_eq!(std::fs::read_to_string(&path)?.lines().count(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_incomplete_last_line_is_skipped() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let path = working_dir.path().join("messages.jsonl");
+
+    let store = MessageStore::open(&path).await?;
+    store.insert(upload_record(1, "pq1")).await?;
+    drop(store);
+    // The bot has crashed while it was writing the second record.
+    let mut journal = std::fs::read_to_string(&path)?;
+    journal.push_str(r#"{"chat_id":-4148908551,"message_id":2,"sta"#);
+    std::fs::write(&path, &journal)?;
+
+    let store = MessageStore::open(&path).await?;
+    assert!(store.find_by_message(-4148908551, 1).await.is_some());
+    assert!(store.find_by_message(-4148908551, 2).await.is_none());
+    store.insert(upload_record(2, "pq2")).await?;
+    drop(store);
+
+    let store = MessageStore::open(&path).await?;
+    assert!(store.find_by_message(-4148908551, 2).await.is_some());
+    drop(store);
+
+    // A broken line in the middle of the journal is not skipped.
+    let journal = std::fs::read_to_string(&path)?;
+    std::fs::write(&path, format!("{{\n{}", journal))?;
+    assert!(MessageStore::open(&path).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_removed_photos_are_forgotten() -> Result<(), anyhow::Error> {
+    let working_dir = tempfile::tempdir()?;
+    let path = working_dir.path().join("messages.jsonl");
+
+    let store = MessageStore::open(&path).await?;
+    store.insert(upload_record(1, "pq1")).await?;
+    store.insert(upload_record(2, "pq2")).await?;
+    store.insert(upload_record(3, "pq1")).await?;
+    store.remove_photos(&[PhotoUID("pq1".to_owned())]).await?;
+    assert!(store.find_by_message(-4148908551, 1).await.is_none());
+    assert_eq!(
+        store.find_by_status_message(-4148908551, 100).await.len(),
+        1
+    );
+    drop(store);
+
+    let store = MessageStore::open(&path).await?;
+    assert!(store.find_by_message(-4148908551, 3).await.is_none());
+    assert!(store.find_by_message(-4148908551, 2).await.is_some());
+    assert_eq!(std::fs::read_to_string(&path)?.lines().count(), 1);
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use telegram2photoprism::{metrics, PhotoPrismServiceError};
+
+#[test]
+fn test_observe_error() {
+    let err = anyhow::Error::from(PhotoPrismServiceError::ArchiveFailed(
+        "Server is down".to_owned(),
+    ))
+    .context("Failed to process upload");
+    metrics().observe_error(&err);
+    // Errors which are not caused by PhotoPrism are not counted.
+    metrics().observe_error(&anyhow::anyhow!("Telegram is down"));
+
+    let metrics = metrics().encode();
+    assert!(
+        metrics.contains("telegram2photoprism_photoprism_errors_total{error=\"ArchiveFailed\"} 1")
+    );
+    assert_eq!(metrics.matches("photoprism_errors_total{").count(), 1);
+}
@@ -3,14 +3,16 @@ use std::pin::Pin;
 use std::time::Duration;
 
 use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
 use futures_util::future::BoxFuture;
-use futures_util::FutureExt;
+use futures_util::{stream, FutureExt, StreamExt};
 use log::debug;
 use testcontainers::{clients, Container, RunnableImage};
 use tokio::time::sleep;
 
-use telegram2photoprism::PhotoPrismPhotoService;
-use telegram2photoprism::PhotoService;
+use telegram2photoprism::{calculate_sha1, PhotoService};
+use telegram2photoprism::{PhotoDetails, PhotoPrismPhotoService};
 
 use crate::photoprism_container::PhotoPrismContainer;
 
@@ -104,6 +106,244 @@ async fn test_add_photo_add_labels() -> Result<(), anyhow::Error> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_list_labels() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            photoprism_service.add_label(&photo_uid, "Han").await?;
+
+            let library_labels = photoprism_service.list_labels().await?;
+            assert!(library_labels.contains(&"Han".to_owned()));
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_remove_label() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            photoprism_service.add_label(&photo_uid, "Han").await?;
+            photoprism_service.add_label(&photo_uid, "Luke").await?;
+
+            photoprism_service.remove_label(&photo_uid, "Luke").await?;
+            let photo_labels = photoprism_service.list_photo_labels(&photo_uid).await?;
+            assert!(photo_labels.contains(&"Han".to_owned()));
+            assert!(!photo_labels.contains(&"Luke".to_owned()));
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_update_photo() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            photoprism_service
+                .update_photo(
+                    &photo_uid,
+                    &PhotoDetails {
+                        title: Some("Tatooine".to_owned()),
+                        description: Some("Sunset over Tatooine".to_owned()),
+                        taken_at: Some(Utc.with_ymd_and_hms(2021, 6, 1, 10, 0, 0).unwrap()),
+                    },
+                )
+                .await?;
+
+            let client = reqwest::Client::new();
+            let get_photo_endpoint = format!("{}/api/v1/photos/{}", photoprism_url, photo_uid.0);
+            let get_photo_json = photoprism_service
+                .send(client.get(get_photo_endpoint))
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+
+            assert_eq!(get_photo_json["Title"], "Tatooine");
+            assert_eq!(get_photo_json["Description"], "Sunset over Tatooine");
+            assert_eq!(get_photo_json["TakenAt"], "2021-06-01T10:00:00Z");
+            assert!(photoprism_service.has_capture_date(&photo_uid).await?);
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_find_photo_by_hash() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let file_hash = calculate_sha1(file_path).await?;
+            assert!(photoprism_service
+                .find_photo_by_hash(&file_hash)
+                .await?
+                .is_none());
+
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            let found_photo_uid = photoprism_service.find_photo_by_hash(&file_hash).await?;
+            assert_eq!(found_photo_uid, Some(photo_uid));
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upload_stream() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            // Sends the file in small chunks, like a download from Telegram.
+            let photo = std::fs::read(file_path)?;
+            let chunks = || {
+                let chunks: Vec<Result<Bytes, anyhow::Error>> = photo
+                    .chunks(1024)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+                stream::iter(chunks).boxed()
+            };
+            let streamed_file = photoprism_service
+                .upload_stream("photo.jpg", chunks())
+                .await?;
+            assert_eq!(streamed_file.sha1, calculate_sha1(file_path).await?);
+            assert!(!streamed_file.already_uploaded);
+            let found_photo_uid = photoprism_service
+                .find_photo_by_hash(&streamed_file.sha1)
+                .await?;
+            assert_eq!(found_photo_uid, Some(streamed_file.photo_uid.clone()));
+
+            let streamed_again = photoprism_service
+                .upload_stream("photo.jpg", chunks())
+                .await?;
+            assert!(streamed_again.already_uploaded);
+            assert_eq!(streamed_again.photo_uid, streamed_file.photo_uid);
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_add_photo_to_album() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            let album = photoprism_service.create_album("Tatooine").await?;
+            photoprism_service
+                .add_to_album(&album.uid, std::slice::from_ref(&photo_uid))
+                .await?;
+
+            let albums = photoprism_service.list_albums().await?;
+            assert!(albums
+                .iter()
+                .any(|a| a.uid == album.uid && a.title == "Tatooine"));
+
+            let client = reqwest::Client::new();
+            let get_photo_endpoint = format!("{}/api/v1/photos/{}", photoprism_url, photo_uid.0);
+            let get_photo_json = photoprism_service
+                .send(client.get(get_photo_endpoint))
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+
+            let photo_albums = get_photo_json["Albums"].as_array().unwrap();
+            assert_ne!(
+                photo_albums.iter().find(|x| x["UID"] == album.uid.as_str()),
+                None
+            );
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_refresh_token() -> Result<(), anyhow::Error> {
     with_fixture(|fixture: &Fixture| {
@@ -144,3 +384,79 @@ async fn test_refresh_token() -> Result<(), anyhow::Error> {
     })
     .await
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_login_again_after_logout() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let user_before_logout = photoprism_service.get_user().await?;
+            let client = reqwest::Client::new();
+            let delete_session_endpoint = format!(
+                "{}/api/v1/session/{}",
+                photoprism_url, user_before_logout.access_token
+            );
+            client
+                .delete(delete_session_endpoint)
+                .header("X-Auth-Token", &user_before_logout.access_token)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            // The session is still cached, so the first request is rejected and replayed after logging in.
+            photoprism_service.list_albums().await?;
+            let user_after_logout = photoprism_service.get_user().await?;
+            assert_ne!(
+                user_before_logout.access_token,
+                user_after_logout.access_token
+            );
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_archive_photo() -> Result<(), anyhow::Error> {
+    with_fixture(|fixture: &Fixture| {
+        async {
+            let photoprism_url = fixture.get_photoprism_url().to_owned();
+            let photoprism_service = PhotoPrismPhotoService::new(
+                photoprism_url.clone(),
+                "admin".to_owned(),
+                "insecure".to_owned(),
+                3600,
+            );
+
+            let file_path = concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/resources/tests/low_quality_photo.jpg"
+            );
+            let photo_uid = photoprism_service.upload_photo(file_path).await?;
+            photoprism_service
+                .archive_photos(std::slice::from_ref(&photo_uid))
+                .await?;
+
+            let client = reqwest::Client::new();
+            let get_photo_endpoint = format!("{}/api/v1/photos/{}", photoprism_url, photo_uid.0);
+            let get_photo_json = photoprism_service
+                .send(client.get(get_photo_endpoint))
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+
+            assert!(!get_photo_json["DeletedAt"].is_null());
+            Ok(())
+        }
+        .boxed()
+    })
+    .await
+}
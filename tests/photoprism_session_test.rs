@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{stream, StreamExt};
+use serde_json::{json, Value};
+
+use telegram2photoprism::{PhotoPrismPhotoService, PhotoPrismServiceError, PhotoService};
+
+const PHOTO_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/resources/tests/low_quality_photo.jpg"
+);
+
+/// Answers the requests of an upload like PhotoPrism, accepting only the token of the last session.
+#[derive(Default)]
+struct PhotoPrismStandIn {
+    sessions: usize,
+    valid_token: Option<String>,
+    album_requests: usize,
+}
+
+type SharedStandIn = Arc<Mutex<PhotoPrismStandIn>>;
+
+fn authorized(stand_in: &SharedStandIn, headers: &HeaderMap) -> StatusCode {
+    let valid_token = stand_in.lock().unwrap().valid_token.clone();
+    match (headers.get("X-Auth-Token"), valid_token) {
+        (Some(token), Some(valid_token)) if token == valid_token.as_str() => StatusCode::OK,
+        _ => StatusCode::UNAUTHORIZED,
+    }
+}
+
+async fn create_session(State(stand_in): State<SharedStandIn>) -> Json<Value> {
+    let mut stand_in = stand_in.lock().unwrap();
+    stand_in.sessions += 1;
+    let token = format!("token-{}", stand_in.sessions);
+    stand_in.valid_token = Some(token.clone());
+    Json(json!({"access_token": token, "user": {"UID": "admin"}}))
+}
+
+async fn upload(State(stand_in): State<SharedStandIn>, headers: HeaderMap, _: Bytes) -> StatusCode {
+    authorized(&stand_in, &headers)
+}
+
+/// Fails like a server which has created the album but crashed before it responded.
+async fn create_album(State(stand_in): State<SharedStandIn>) -> StatusCode {
+    stand_in.lock().unwrap().album_requests += 1;
+    StatusCode::BAD_GATEWAY
+}
+
+async fn search_photos(
+    State(stand_in): State<SharedStandIn>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    (
+        authorized(&stand_in, &headers),
+        Json(json!([{"UID": "photo-1"}])),
+    )
+}
+
+async fn start_stand_in(stand_in: SharedStandIn) -> String {
+    let router = Router::new()
+        .route("/api/v1/session", post(create_session))
+        .route("/api/v1/users/:uid/upload/:token", post(upload).put(upload))
+        .route("/api/v1/photos", get(search_photos))
+        .route("/api/v1/albums", post(create_album))
+        .with_state(stand_in);
+
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let address = server.local_addr();
+    tokio::spawn(server);
+    format!("http://{}", address)
+}
+
+/// Logs in and then ends the session at the server, while the service still has it cached.
+async fn logged_out_service(stand_in: &SharedStandIn) -> PhotoPrismPhotoService {
+    let photoprism_url = start_stand_in(stand_in.clone()).await;
+    let photo_service = PhotoPrismPhotoService::new(
+        photoprism_url,
+        "admin".to_owned(),
+        "insecure".to_owned(),
+        3600,
+    );
+    photo_service.get_user().await.unwrap();
+    stand_in.lock().unwrap().valid_token = None;
+    photo_service
+}
+
+#[tokio::test]
+async fn test_upload_photo_after_logout() -> Result<(), anyhow::Error> {
+    let stand_in = SharedStandIn::default();
+    let photo_service = logged_out_service(&stand_in).await;
+
+    let photo_uid = photo_service.upload_photo(PHOTO_PATH).await?;
+    assert_eq!(photo_uid.0, "photo-1");
+    assert_eq!(stand_in.lock().unwrap().sessions, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_stream_after_logout() -> Result<(), anyhow::Error> {
+    let stand_in = SharedStandIn::default();
+    let photo_service = logged_out_service(&stand_in).await;
+    let photo = || stream::iter([Ok(Bytes::from(std::fs::read(PHOTO_PATH).unwrap()))]).boxed();
+
+    // A stream can not be sent again, but the next upload logs in again.
+    let result = photo_service.upload_stream("photo.jpg", photo()).await;
+    assert!(matches!(
+        result,
+        Err(PhotoPrismServiceError::SessionRejected(_))
+    ));
+    let streamed_file = photo_service.upload_stream("photo.jpg", photo()).await?;
+    assert_eq!(streamed_file.photo_uid.0, "photo-1");
+    assert_eq!(stand_in.lock().unwrap().sessions, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_post_is_not_sent_again() -> Result<(), anyhow::Error> {
+    let stand_in = SharedStandIn::default();
+    let photoprism_url = start_stand_in(stand_in.clone()).await;
+    let photo_service = PhotoPrismPhotoService::new(
+        photoprism_url,
+        "admin".to_owned(),
+        "insecure".to_owned(),
+        3600,
+    );
+
+    // The album may have been created, so sending the request again could create another one.
+    assert!(photo_service.create_album("Endor").await.is_err());
+    assert_eq!(stand_in.lock().unwrap().album_requests, 1);
+    Ok(())
+}